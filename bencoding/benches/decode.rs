@@ -0,0 +1,37 @@
+use bencoding::{decode_bencode, decode_bencode_ref};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// Single-file torrent with `piece_count` fake SHA-1 hashes in `pieces`.
+fn synthetic_torrent(piece_count: usize) -> Vec<u8> {
+    let pieces: Vec<u8> = (0..piece_count * 20).map(|i| (i * 31 % 251) as u8).collect();
+    let mut data = Vec::new();
+    data.extend_from_slice(b"d8:announce30:udp://tracker.example.org:13374:infod6:lengthi");
+    data.extend_from_slice((piece_count as u64 * 16384).to_string().as_bytes());
+    data.extend_from_slice(b"e4:name8:data.bin12:piece lengthi16384e6:pieces");
+    data.extend_from_slice(pieces.len().to_string().as_bytes());
+    data.push(b':');
+    data.extend_from_slice(&pieces);
+    data.extend_from_slice(b"ee");
+    data
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    let inputs = [
+        ("test2.torrent", std::fs::read("test2.torrent").expect("run from the crate root")),
+        ("synthetic_8mb", synthetic_torrent(8 * 1024 * 1024 / 20)),
+    ];
+    for (name, data) in inputs.iter() {
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(BenchmarkId::new("owned", name), data, |b, data| {
+            b.iter(|| decode_bencode(black_box(data)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("borrowed", name), data, |b, data| {
+            b.iter(|| decode_bencode_ref(black_box(data)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
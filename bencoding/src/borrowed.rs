@@ -0,0 +1,80 @@
+use crate::Bencode;
+
+/// Borrowed counterpart of [`Bencode`], byte strings point straight into the
+/// decoded buffer instead of being copied.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BencodeRef<'a> {
+    String(&'a str),
+    Integer(i64),
+    List(Vec<BencodeRef<'a>>),
+    Dictionary(Vec<(BencodeRef<'a>, BencodeRef<'a>)>),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> BencodeRef<'a> {
+    /// Raw bytes of a `String` or `Bytes` value.
+    pub fn as_raw_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            BencodeRef::String(value) => Some(value.as_bytes()),
+            BencodeRef::Bytes(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn into_owned(self) -> Bencode {
+        match self {
+            BencodeRef::String(value) => Bencode::String(value.to_string()),
+            BencodeRef::Integer(value) => Bencode::Integer(value),
+            BencodeRef::List(list) => {
+                Bencode::List(list.into_iter().map(BencodeRef::into_owned).collect())
+            }
+            BencodeRef::Dictionary(dict) => Bencode::Dictionary(
+                dict.into_iter()
+                    .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                    .collect(),
+            ),
+            BencodeRef::Bytes(value) => Bencode::Bytes(value.to_vec()),
+        }
+    }
+}
+
+impl<'a> From<BencodeRef<'a>> for Bencode {
+    fn from(value: BencodeRef<'a>) -> Self {
+        value.into_owned()
+    }
+}
+
+impl<'a> From<&'a Bencode> for BencodeRef<'a> {
+    fn from(value: &'a Bencode) -> Self {
+        match value {
+            Bencode::String(value) => BencodeRef::String(value),
+            Bencode::Integer(value) => BencodeRef::Integer(*value),
+            Bencode::List(list) => BencodeRef::List(list.iter().map(BencodeRef::from).collect()),
+            Bencode::Dictionary(dict) => BencodeRef::Dictionary(
+                dict.iter()
+                    .map(|(key, value)| (BencodeRef::from(key), BencodeRef::from(value)))
+                    .collect(),
+            ),
+            Bencode::Bytes(value) => BencodeRef::Bytes(value),
+        }
+    }
+}
+
+#[test]
+fn test_decode_borrowed() {
+    let data = b"d4:name3:abc6:pieces2:\xff\x00e";
+    let decoded = crate::decode_bencode_ref(data).unwrap();
+    match &decoded {
+        BencodeRef::Dictionary(dict) => {
+            assert_eq!(dict[0], (BencodeRef::String("name"), BencodeRef::String("abc")));
+            let pieces = dict[1].1.as_raw_bytes().unwrap();
+            assert_eq!(pieces, b"\xff\x00");
+            // Slices point into the input, nothing was copied.
+            assert_eq!(pieces.as_ptr(), data[22..].as_ptr());
+        }
+        _ => panic!("expected dictionary"),
+    }
+    let owned = decoded.clone().into_owned();
+    assert_eq!(owned, crate::decode_bencode(data).unwrap());
+    assert_eq!(BencodeRef::from(&owned), decoded);
+}
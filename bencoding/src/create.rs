@@ -0,0 +1,590 @@
+//! Building `.torrent` files from local data.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha1::{Digest, Sha1};
+
+use crate::merkle;
+use crate::metainfo::PIECE_HASH_LEN;
+use crate::{Bencode, InfoHash, InfoHashV2, MetaVersion};
+
+pub const MIN_PIECE_LENGTH: u64 = 16 * 1024;
+pub const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+/// Piece count the automatic piece length aims for.
+const TARGET_PIECE_COUNT: u64 = 1500;
+
+#[derive(Debug)]
+pub enum CreateError {
+    Io(io::Error),
+    /// Path is an empty file or a directory without files.
+    NoData,
+    /// Piece length that is not a power of two of at least 16 KiB.
+    InvalidPieceLength(u64),
+}
+
+impl fmt::Display for CreateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CreateError::Io(err) => write!(f, "{err}"),
+            CreateError::NoData => write!(f, "there is no data to put into the torrent"),
+            CreateError::InvalidPieceLength(length) => {
+                write!(f, "piece length {length} is not a power of two of at least 16 KiB")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CreateError {}
+
+impl From<io::Error> for CreateError {
+    fn from(value: io::Error) -> Self {
+        CreateError::Io(value)
+    }
+}
+
+/// Settings of a new torrent, set with the chained setters and turned into
+/// a torrent by [`TorrentCreator::create`].
+#[derive(Debug, Clone, Default)]
+pub struct TorrentCreator {
+    path: PathBuf,
+    piece_length: Option<u64>,
+    trackers: Vec<Vec<String>>,
+    web_seeds: Vec<String>,
+    comment: Option<String>,
+    created_by: Option<String>,
+    creation_date: Option<i64>,
+    private: bool,
+    source: Option<String>,
+    threads: Option<usize>,
+    version: MetaVersion,
+}
+
+/// A file of the torrent, relative to the root directory.
+struct InputFile {
+    /// `None` for padding files.
+    full_path: Option<PathBuf>,
+    path: Vec<String>,
+    length: u64,
+}
+
+/// Finished torrent: the tree, its canonical encoding and the info-hash.
+#[derive(Debug, Clone)]
+pub struct CreatedTorrent {
+    pub torrent: Bencode,
+    pub bytes: Vec<u8>,
+    /// Same meaning as [`crate::Metainfo::info_hash`].
+    pub info_hash: InfoHash,
+    pub info_hash_v2: Option<InfoHashV2>,
+}
+
+impl CreatedTorrent {
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        std::fs::write(path, &self.bytes)
+    }
+}
+
+impl TorrentCreator {
+    /// Torrent of a single file or of every file below a directory.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        TorrentCreator {
+            path: path.as_ref().to_path_buf(),
+            created_by: Some(concat!("bencoding/", env!("CARGO_PKG_VERSION")).to_string()),
+            creation_date: Some(
+                SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs() as i64),
+            ),
+            ..Default::default()
+        }
+    }
+
+    /// Fixed piece length, chosen from the total size when not set.
+    pub fn piece_length(mut self, piece_length: u64) -> Self {
+        self.piece_length = Some(piece_length);
+        self
+    }
+
+    /// Adds a tier of trackers (BEP 12). The first tracker also becomes
+    /// `announce` for clients without tier support.
+    pub fn tracker_tier<I: IntoIterator<Item = S>, S: Into<String>>(mut self, tier: I) -> Self {
+        let tier: Vec<String> = tier.into_iter().map(Into::into).collect();
+        if !tier.is_empty() {
+            self.trackers.push(tier);
+        }
+        self
+    }
+
+    /// Adds a tracker as a tier of its own.
+    pub fn tracker<S: Into<String>>(self, url: S) -> Self {
+        self.tracker_tier([url])
+    }
+
+    /// Adds an HTTP seed (BEP 19).
+    pub fn web_seed<S: Into<String>>(mut self, url: S) -> Self {
+        self.web_seeds.push(url.into());
+        self
+    }
+
+    pub fn comment<S: Into<String>>(mut self, comment: S) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    pub fn created_by(mut self, created_by: Option<String>) -> Self {
+        self.created_by = created_by;
+        self
+    }
+
+    /// `None` leaves the date out, so the same input gives the same file.
+    pub fn creation_date(mut self, creation_date: Option<i64>) -> Self {
+        self.creation_date = creation_date;
+        self
+    }
+
+    pub fn private(mut self, private: bool) -> Self {
+        self.private = private;
+        self
+    }
+
+    /// `source` tag inside `info`, gives cross-seeded copies of the same
+    /// data different info-hashes.
+    pub fn source<S: Into<String>>(mut self, source: S) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// v1 (the default), v2-only or hybrid torrent (BEP 52).
+    pub fn version(mut self, version: MetaVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Hashing threads, defaults to the available parallelism.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads.max(1));
+        self
+    }
+
+    pub fn create(&self) -> Result<CreatedTorrent, CreateError> {
+        let metadata = std::fs::metadata(&self.path)?;
+        let name = match self.path.canonicalize()?.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => return Err(CreateError::Io(io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))),
+        };
+        let files = if metadata.is_dir() {
+            let mut files = Vec::new();
+            collect_files(&self.path, &mut Vec::new(), &mut files)?;
+            files
+        } else {
+            vec![InputFile { full_path: Some(self.path.clone()), path: vec![name.clone()], length: metadata.len() }]
+        };
+        let total_length: u64 = files.iter().map(|file| file.length).sum();
+        if total_length == 0 {
+            return Err(CreateError::NoData);
+        }
+        let piece_length = match self.piece_length {
+            Some(length) if length < MIN_PIECE_LENGTH || !length.is_power_of_two() => {
+                return Err(CreateError::InvalidPieceLength(length))
+            }
+            Some(length) => length,
+            None => auto_piece_length(total_length),
+        };
+        let threads = self
+            .threads
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |threads| threads.get()));
+
+        let insert = |dict: &mut Bencode, key: &[u8], value: Bencode| {
+            dict.insert(key, value).expect("torrent values are dictionaries");
+        };
+        let mut info = Bencode::Dictionary(Vec::new());
+        let mut piece_layers = Bencode::Dictionary(Vec::new());
+        insert(&mut info, b"name", name.as_str().into());
+        insert(&mut info, b"piece length", Bencode::Integer(piece_length as i64));
+        if self.private {
+            insert(&mut info, b"private", Bencode::Integer(1));
+        }
+        if let Some(source) = &self.source {
+            insert(&mut info, b"source", source.as_str().into());
+        }
+
+        if self.version != MetaVersion::V1 {
+            let hashes = hash_files_v2(&files, piece_length, threads)?;
+            let mut tree = Bencode::Dictionary(Vec::new());
+            for (file, (pieces_root, layer)) in files.iter().zip(hashes) {
+                let mut entry = Bencode::Dictionary(Vec::new());
+                insert(&mut entry, b"length", Bencode::Integer(file.length as i64));
+                if let Some(root) = pieces_root {
+                    insert(&mut entry, b"pieces root", Bencode::Bytes(root.to_vec()));
+                    if !layer.is_empty() {
+                        insert(&mut piece_layers, &root, Bencode::Bytes(layer.concat()));
+                    }
+                }
+                let mut node = &mut tree;
+                for part in &file.path {
+                    if node.get(part).is_none() {
+                        insert(node, part.as_bytes(), Bencode::Dictionary(Vec::new()));
+                    }
+                    node = node.get_mut(part).unwrap();
+                }
+                insert(node, b"", entry);
+            }
+            insert(&mut info, b"meta version", Bencode::Integer(2));
+            insert(&mut info, b"file tree", tree);
+        }
+
+        if self.version != MetaVersion::V2 {
+            // Hybrid torrents align every file to a piece boundary so the
+            // v1 pieces line up with the per-file v2 trees.
+            let files = match self.version {
+                MetaVersion::Hybrid => with_padding(files, piece_length),
+                _ => files,
+            };
+            let v1_length = files.iter().map(|file| file.length).sum();
+            let pieces = hash_pieces(&files, v1_length, piece_length, threads)?;
+            insert(&mut info, b"pieces", Bencode::Bytes(pieces.concat()));
+            if metadata.is_dir() {
+                let files = files
+                    .into_iter()
+                    .map(|file| {
+                        let mut entry = Bencode::Dictionary(Vec::new());
+                        insert(&mut entry, b"length", Bencode::Integer(file.length as i64));
+                        insert(&mut entry, b"path", string_list(&file.path));
+                        if file.full_path.is_none() {
+                            insert(&mut entry, b"attr", "p".into());
+                        }
+                        entry
+                    })
+                    .collect::<Vec<_>>();
+                insert(&mut info, b"files", Bencode::List(files));
+            } else {
+                insert(&mut info, b"length", Bencode::Integer(total_length as i64));
+            }
+        }
+
+        let info_bytes = info.to_bencode_bytes()?;
+        let info_hash_v2 = (self.version != MetaVersion::V1).then(|| InfoHashV2::from_info_bytes(&info_bytes));
+        let info_hash = match info_hash_v2 {
+            Some(hash) if self.version == MetaVersion::V2 => hash.truncated(),
+            _ => InfoHash::from_info_bytes(&info_bytes),
+        };
+
+        let mut torrent = Bencode::Dictionary(Vec::new());
+        if let Some(announce) = self.trackers.first().and_then(|tier| tier.first()) {
+            insert(&mut torrent, b"announce", announce.as_str().into());
+        }
+        if self.trackers.iter().map(Vec::len).sum::<usize>() > 1 {
+            let tiers = self.trackers.iter().map(|tier| string_list(tier)).collect();
+            insert(&mut torrent, b"announce-list", Bencode::List(tiers));
+        }
+        match self.web_seeds.as_slice() {
+            [] => {}
+            [seed] => insert(&mut torrent, b"url-list", seed.as_str().into()),
+            seeds => insert(&mut torrent, b"url-list", string_list(seeds)),
+        }
+        if let Some(comment) = &self.comment {
+            insert(&mut torrent, b"comment", comment.as_str().into());
+        }
+        if let Some(created_by) = &self.created_by {
+            insert(&mut torrent, b"created by", created_by.as_str().into());
+        }
+        if let Some(date) = self.creation_date {
+            insert(&mut torrent, b"creation date", Bencode::Integer(date));
+        }
+        if piece_layers.as_dict().is_some_and(|layers| !layers.is_empty()) {
+            insert(&mut torrent, b"piece layers", piece_layers);
+        }
+        insert(&mut torrent, b"info", info);
+
+        let bytes = torrent.to_bencode_bytes()?;
+        Ok(CreatedTorrent { torrent, bytes, info_hash, info_hash_v2 })
+    }
+}
+
+fn string_list(strings: &[String]) -> Bencode {
+    Bencode::List(strings.iter().map(|string| string.as_str().into()).collect())
+}
+
+/// Smallest power of two that keeps the torrent around
+/// [`TARGET_PIECE_COUNT`] pieces, within the usual limits.
+pub fn auto_piece_length(total_length: u64) -> u64 {
+    total_length
+        .div_ceil(TARGET_PIECE_COUNT)
+        .next_power_of_two()
+        .clamp(MIN_PIECE_LENGTH, MAX_PIECE_LENGTH)
+}
+
+/// Regular files below `dir`, sorted by path so the result does not depend
+/// on the order the file system lists them in. This is also the order of
+/// the v2 `file tree`, whose keys are sorted the same way. Symlinks are
+/// skipped, they could loop back into a parent or reach outside `dir`.
+fn collect_files(dir: &Path, prefix: &mut Vec<String>, files: &mut Vec<InputFile>) -> io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let full_path = entry.path();
+        let metadata = std::fs::symlink_metadata(&full_path)?;
+        prefix.push(entry.file_name().to_string_lossy().into_owned());
+        if metadata.is_dir() {
+            collect_files(&full_path, prefix, files)?;
+        } else if metadata.is_file() {
+            files.push(InputFile { full_path: Some(full_path), path: prefix.clone(), length: metadata.len() });
+        }
+        prefix.pop();
+    }
+    Ok(())
+}
+
+/// BEP 47 padding files after every file that does not end on a piece
+/// boundary, except the last one.
+fn with_padding(files: Vec<InputFile>, piece_length: u64) -> Vec<InputFile> {
+    let count = files.len();
+    let mut padded = Vec::with_capacity(count * 2);
+    for (i, file) in files.into_iter().enumerate() {
+        let remainder = file.length % piece_length;
+        padded.push(file);
+        if remainder != 0 && i + 1 < count {
+            let length = piece_length - remainder;
+            padded.push(InputFile { full_path: None, path: vec![".pad".to_string(), length.to_string()], length });
+        }
+    }
+    padded
+}
+
+/// Runs `task` for every index on `threads` workers. Workers take the next
+/// index as they finish, so a slow disk region does not stall the others.
+fn parallel_map<T, F>(count: usize, threads: usize, task: F) -> io::Result<Vec<T>>
+where
+    T: Send,
+    F: Fn(usize, &mut Vec<u8>) -> io::Result<T> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..count).map(|_| None).collect::<Vec<Option<T>>>());
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(count))
+            .map(|_| {
+                scope.spawn(|| -> io::Result<()> {
+                    let mut buffer = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= count {
+                            return Ok(());
+                        }
+                        let result = task(index, &mut buffer)?;
+                        results.lock().unwrap()[index] = Some(result);
+                    }
+                })
+            })
+            .collect();
+        workers.into_iter().try_for_each(|worker| worker.join().expect("hashing thread panicked"))
+    })?;
+    Ok(results.into_inner().unwrap().into_iter().map(|result| result.unwrap()).collect())
+}
+
+/// SHA-1 of every piece of the files laid end to end.
+fn hash_pieces(
+    files: &[InputFile],
+    total_length: u64,
+    piece_length: u64,
+    threads: usize,
+) -> io::Result<Vec<[u8; PIECE_HASH_LEN]>> {
+    let piece_count = total_length.div_ceil(piece_length) as usize;
+    parallel_map(piece_count, threads, |index, buffer| {
+        let start = index as u64 * piece_length;
+        buffer.resize((total_length - start).min(piece_length) as usize, 0);
+        read_range(files, start, buffer)?;
+        Ok(Sha1::digest(&buffer[..]).into())
+    })
+}
+
+/// `pieces root` and piece layer of every file, each piece of each file
+/// is a separate task.
+#[allow(clippy::type_complexity)]
+fn hash_files_v2(
+    files: &[InputFile],
+    piece_length: u64,
+    threads: usize,
+) -> io::Result<Vec<(Option<merkle::Hash>, Vec<merkle::Hash>)>> {
+    let tasks: Vec<(usize, u64)> = files
+        .iter()
+        .enumerate()
+        .flat_map(|(i, file)| (0..file.length.div_ceil(piece_length)).map(move |piece| (i, piece)))
+        .collect();
+    let piece_hashes = parallel_map(tasks.len(), threads, |index, buffer| {
+        let (file, piece) = tasks[index];
+        let file = &files[file];
+        let start = piece * piece_length;
+        buffer.resize((file.length - start).min(piece_length) as usize, 0);
+        read_range(std::slice::from_ref(file), start, buffer)?;
+        let blocks: Vec<merkle::Hash> = buffer.chunks(merkle::BLOCK_SIZE as usize).map(merkle::hash_block).collect();
+        Ok(merkle::piece_hash(&blocks, file.length, piece_length))
+    })?;
+
+    let mut layers: Vec<Vec<merkle::Hash>> = vec![Vec::new(); files.len()];
+    for ((file, _), hash) in tasks.into_iter().zip(piece_hashes) {
+        layers[file].push(hash);
+    }
+    Ok(layers
+        .into_iter()
+        .map(|layer| match layer.len() {
+            0 => (None, layer),
+            1 => (Some(layer[0]), Vec::new()),
+            _ => (Some(merkle::root_from_layer(&layer, piece_length)), layer),
+        })
+        .collect())
+}
+
+/// Fills `buffer` with the bytes at `offset` of the concatenated files,
+/// padding files read as zeros.
+fn read_range(files: &[InputFile], mut offset: u64, mut buffer: &mut [u8]) -> io::Result<()> {
+    for file in files {
+        if buffer.is_empty() {
+            break;
+        }
+        if offset >= file.length {
+            offset -= file.length;
+            continue;
+        }
+        let length = buffer.len().min((file.length - offset) as usize);
+        match &file.full_path {
+            Some(full_path) => {
+                let mut handle = File::open(full_path)?;
+                handle.seek(SeekFrom::Start(offset))?;
+                handle.read_exact(&mut buffer[..length])?;
+            }
+            None => buffer[..length].fill(0),
+        }
+        buffer = &mut buffer[length..];
+        offset = 0;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_torrent() {
+    let root = std::env::temp_dir().join(format!("bencoding-create-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("data/sub")).unwrap();
+    let a: Vec<u8> = (0..40_000u32).map(|i| i as u8).collect();
+    let b: Vec<u8> = (0..10_000u32).map(|i| (i * 7) as u8).collect();
+    std::fs::write(root.join("data/sub/b.bin"), &b).unwrap();
+    std::fs::write(root.join("data/a.bin"), &a).unwrap();
+
+    let created = TorrentCreator::new(root.join("data"))
+        .tracker_tier(["udp://one:80", "udp://two:80"])
+        .tracker("http://three/announce")
+        .web_seed("http://seed/")
+        .comment("test data")
+        .private(true)
+        .source("unit")
+        .creation_date(None)
+        .threads(3)
+        .create()
+        .unwrap();
+    let file = root.join("data.torrent");
+    created.write_to_file(&file).unwrap();
+
+    let metainfo = crate::read_metainfo_from_file(file.to_str().unwrap()).await.unwrap();
+    assert_eq!(metainfo.info_hash, created.info_hash);
+    assert_eq!(metainfo.info.piece_length, MIN_PIECE_LENGTH);
+    assert!(metainfo.info.private);
+    assert_eq!(metainfo.announce.as_deref(), Some("udp://one:80"));
+    assert_eq!(metainfo.announce_list.len(), 2);
+    assert_eq!(metainfo.comment.as_deref(), Some("test data"));
+    let data = [a, b].concat();
+    for (piece, chunk) in metainfo.info.pieces.iter().zip(data.chunks(MIN_PIECE_LENGTH as usize)) {
+        assert_eq!(piece[..], Sha1::digest(chunk)[..]);
+    }
+    match &metainfo.info.layout {
+        crate::FileLayout::Multi { files } => {
+            assert_eq!(files[0].path, ["a.bin"]);
+            assert_eq!(files[1].path, ["sub", "b.bin"]);
+        }
+        crate::FileLayout::Single { .. } => panic!("expected multi-file torrent"),
+    }
+
+    let decoded = crate::read_torrent_from_file(file.to_str().unwrap()).await.unwrap();
+    assert_eq!(decoded.query("info.source").and_then(Bencode::as_str), Some("unit"));
+    assert_eq!(decoded.get("url-list").and_then(Bencode::as_str), Some("http://seed/"));
+    assert_eq!(metainfo.web_seeds, ["http://seed/"]);
+    let strict = crate::decode_bencode_with_options(&created.bytes, &crate::DecodeOptions::strict());
+    assert!(strict.is_ok());
+    std::fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(auto_piece_length(1), MIN_PIECE_LENGTH);
+    assert_eq!(auto_piece_length(4 << 30), 4 << 20);
+    assert_eq!(auto_piece_length(u64::MAX / 2), MAX_PIECE_LENGTH);
+}
+
+#[tokio::test]
+async fn test_create_hybrid_and_v2() {
+    let root = std::env::temp_dir().join(format!("bencoding-create-v2-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("data/sub")).unwrap();
+    let a: Vec<u8> = (0..40_000u32).map(|i| (i % 253) as u8).collect();
+    let b: Vec<u8> = (0..10_000u32).map(|i| (i % 7) as u8).collect();
+    std::fs::write(root.join("data/a.bin"), &a).unwrap();
+    std::fs::write(root.join("data/sub/b.bin"), &b).unwrap();
+    std::fs::write(root.join("data/sub/empty"), b"").unwrap();
+
+    let created = TorrentCreator::new(root.join("data")).version(MetaVersion::Hybrid).create().unwrap();
+    let metainfo = crate::Metainfo::from_bytes(&created.bytes).unwrap();
+    assert_eq!(metainfo.info.version, MetaVersion::Hybrid);
+    assert_eq!(metainfo.info_hash, created.info_hash);
+    assert_eq!(metainfo.info_hash_v2, created.info_hash_v2);
+    assert_eq!(metainfo.swarm_hashes(), vec![created.info_hash, created.info_hash_v2.unwrap().truncated()]);
+    let (root_a, layer_a) = merkle::file_hashes(&a, MIN_PIECE_LENGTH);
+    assert_eq!(metainfo.info.files_v2[0].pieces_root, Some(root_a));
+    assert_eq!(metainfo.piece_layers.get(&root_a), Some(&layer_a));
+    assert_eq!(metainfo.info.files_v2[2].pieces_root, None);
+    // v1 pieces run over the files padded to piece boundaries.
+    let padded = [&a[..], &[0; 9152], &b[..], &[0; 6384]].concat();
+    let v1: Vec<[u8; PIECE_HASH_LEN]> =
+        padded.chunks(MIN_PIECE_LENGTH as usize).map(|piece| Sha1::digest(piece).into()).collect();
+    assert_eq!(metainfo.info.pieces, v1);
+    match &metainfo.info.layout {
+        crate::FileLayout::Multi { files } => {
+            let padding: Vec<_> = files.iter().filter(|file| file.padding).map(|file| file.length).collect();
+            assert_eq!(padding, [9152, 6384]);
+        }
+        crate::FileLayout::Single { .. } => panic!("expected multi-file torrent"),
+    }
+
+    // Layers that don't hash up to the pieces root are rejected.
+    let mut tampered = created.torrent.clone();
+    tampered.get_mut("piece layers").and_then(Bencode::as_dict_mut).unwrap()[0].1 = Bencode::Bytes(vec![0; 96]);
+    let info_bytes = created.torrent.get("info").unwrap().to_bencode_bytes().unwrap();
+    assert!(matches!(
+        crate::Metainfo::from_bencode(&tampered, &info_bytes),
+        Err(crate::MetainfoError::InvalidValue { .. })
+    ));
+
+    let created = TorrentCreator::new(root.join("data/a.bin")).version(MetaVersion::V2).create().unwrap();
+    let metainfo = crate::Metainfo::from_bytes(&created.bytes).unwrap();
+    assert_eq!(metainfo.info.version, MetaVersion::V2);
+    assert_eq!(metainfo.info_hash, created.info_hash_v2.unwrap().truncated());
+    assert_eq!(metainfo.info_hash_v1(), None);
+    assert_eq!(metainfo.info.layout, crate::FileLayout::Single { length: 40_000 });
+    assert!(metainfo.info.pieces.is_empty());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_create_skips_symlinks() {
+    let root = std::env::temp_dir().join(format!("bencoding-create-links-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("data")).unwrap();
+    std::fs::write(root.join("data/a.bin"), [1; 100]).unwrap();
+    std::fs::write(root.join("outside.bin"), [2; 100]).unwrap();
+    std::os::unix::fs::symlink(root.join("data"), root.join("data/loop")).unwrap();
+    std::os::unix::fs::symlink(root.join("outside.bin"), root.join("data/outside.bin")).unwrap();
+
+    let created = TorrentCreator::new(root.join("data")).create().unwrap();
+    let metainfo = crate::Metainfo::from_bytes(&created.bytes).unwrap();
+    assert_eq!(metainfo.info.total_length(), 100);
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,268 @@
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+use crate::error::SerdeError;
+use crate::{decode_bencode_ref_with_options, Bencode, BencodeRef, DecodeOptions};
+
+/// Deserializes `T` from bencode, strings and byte strings of the result
+/// may borrow from `data`.
+pub fn from_bytes<'de, T: de::Deserialize<'de>>(data: &'de [u8]) -> Result<T, SerdeError> {
+    from_bytes_with_options(data, &DecodeOptions::default())
+}
+
+pub fn from_bytes_with_options<'de, T: de::Deserialize<'de>>(
+    data: &'de [u8],
+    options: &DecodeOptions,
+) -> Result<T, SerdeError> {
+    let value = decode_bencode_ref_with_options(data, options)?;
+    T::deserialize(Deserializer::new(value))
+}
+
+/// Deserializes `T` out of an already decoded tree.
+pub fn from_bencode<T: DeserializeOwned>(value: &Bencode) -> Result<T, SerdeError> {
+    T::deserialize(Deserializer::new(BencodeRef::from(value)))
+}
+
+/// Deserializer over one decoded value.
+pub struct Deserializer<'de> {
+    value: BencodeRef<'de>,
+}
+
+impl<'de> Deserializer<'de> {
+    pub fn new(value: BencodeRef<'de>) -> Self {
+        Deserializer { value }
+    }
+}
+
+fn unexpected<'a>(value: &'a BencodeRef) -> de::Unexpected<'a> {
+    match value {
+        BencodeRef::String(value) => de::Unexpected::Str(value),
+        BencodeRef::Integer(value) => de::Unexpected::Signed(*value),
+        BencodeRef::List(_) => de::Unexpected::Seq,
+        BencodeRef::Dictionary(_) => de::Unexpected::Map,
+        BencodeRef::Bytes(value) => de::Unexpected::Bytes(value),
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            BencodeRef::String(value) => visitor.visit_borrowed_str(value),
+            BencodeRef::Integer(value) => visitor.visit_i64(value),
+            BencodeRef::Bytes(value) => visitor.visit_borrowed_bytes(value),
+            BencodeRef::List(list) => visitor.visit_seq(SeqAccess { items: list.into_iter() }),
+            BencodeRef::Dictionary(dict) => visitor.visit_map(MapAccess { entries: dict.into_iter(), value: None }),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            BencodeRef::Integer(0) => visitor.visit_bool(false),
+            BencodeRef::Integer(1) => visitor.visit_bool(true),
+            ref value => Err(de::Error::invalid_type(unexpected(value), &"integer 0 or 1")),
+        }
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(SerdeError::UnsupportedType("f32"))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(SerdeError::UnsupportedType("f64"))
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value.as_raw_bytes() {
+            Some(bytes) => visitor.visit_borrowed_bytes(bytes),
+            None => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    /// Absent dictionary keys are `None`, anything that is present is `Some`.
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value {
+            BencodeRef::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            BencodeRef::Dictionary(mut dict) if dict.len() == 1 => {
+                let (variant, value) = dict.pop().unwrap();
+                visitor.visit_enum(EnumAccess { variant, value })
+            }
+            ref value => Err(de::Error::invalid_type(unexpected(value), &"string or single entry dictionary")),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 char str string
+        seq tuple tuple_struct map struct identifier
+    }
+}
+
+struct SeqAccess<'de> {
+    items: std::vec::IntoIter<BencodeRef<'de>>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'de> {
+    type Error = SerdeError;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+        match self.items.next() {
+            Some(value) => seed.deserialize(Deserializer::new(value)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+struct MapAccess<'de> {
+    entries: std::vec::IntoIter<(BencodeRef<'de>, BencodeRef<'de>)>,
+    value: Option<BencodeRef<'de>>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess<'de> {
+    type Error = SerdeError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(Deserializer::new(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(Deserializer::new(value)),
+            None => Err(SerdeError::Message("value requested before its key".to_string())),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct EnumAccess<'de> {
+    variant: BencodeRef<'de>,
+    value: BencodeRef<'de>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess<'de> {
+    type Error = SerdeError;
+    type Variant = Deserializer<'de>;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let variant = seed.deserialize(Deserializer::new(self.variant))?;
+        Ok((variant, Deserializer::new(self.value)))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer<'de> {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Self::Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+#[test]
+fn test_deserialize_round_trip() {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Event {
+        Started,
+        Completed { downloaded: u64 },
+        Error(String),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Response<'a> {
+        interval: u32,
+        #[serde(rename = "min interval")]
+        min_interval: Option<u32>,
+        #[serde(with = "serde_bytes")]
+        peers: &'a [u8],
+        events: Vec<Event>,
+        private: bool,
+    }
+
+    let response = Response {
+        interval: 1800,
+        min_interval: None,
+        peers: &[127, 0, 0, 1, 0x1a, 0xe1],
+        events: vec![
+            Event::Started,
+            Event::Completed { downloaded: 10 },
+            Event::Error("x".to_string()),
+        ],
+        private: false,
+    };
+    let encoded = crate::to_bytes(&response).unwrap();
+    let decoded: Response = from_bytes(&encoded).unwrap();
+    assert_eq!(decoded, response);
+    // Byte fields borrow straight from the input.
+    assert!(encoded.as_ptr_range().contains(&decoded.peers.as_ptr()));
+
+    let value: Bencode = from_bytes(&encoded).unwrap();
+    assert_eq!(crate::to_bytes(&value).unwrap(), encoded);
+    assert!(from_bytes::<Response>(b"d8:intervali-1ee").is_err());
+}
@@ -0,0 +1,198 @@
+use std::io::{self, Write};
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::Bencode;
+
+/// Piece of encoder output, produced in order by [`Tokens`].
+enum Token<'a> {
+    Byte(u8),
+    Integer(i64),
+    /// Length-prefixed byte string.
+    String(&'a [u8]),
+}
+
+enum Frame<'a> {
+    Value(&'a Bencode),
+    End,
+}
+
+/// Walks a value depth-first without recursion so the same traversal
+/// drives both the blocking and the async writer.
+struct Tokens<'a> {
+    stack: Vec<Frame<'a>>,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(value: &'a Bencode) -> Self {
+        Tokens { stack: vec![Frame::Value(value)] }
+    }
+
+    /// Queues dictionary entries ordered by the raw bytes of their keys, as
+    /// BEP 3 requires. Decoded dictionaries and those filled through
+    /// `insert` are sorted already and go straight onto the stack, only
+    /// hand-built ones are sorted through a temporary list of references.
+    fn push_entries(&mut self, values: &'a [(Bencode, Bencode)]) -> io::Result<()> {
+        for (key, _) in values {
+            key_bytes(key)?;
+        }
+        self.stack.push(Frame::End);
+        if values.windows(2).all(|pair| sort_key(&pair[0]) < sort_key(&pair[1])) {
+            for (key, value) in values.iter().rev() {
+                self.stack.push(Frame::Value(value));
+                self.stack.push(Frame::Value(key));
+            }
+            return Ok(());
+        }
+        let mut entries: Vec<&(Bencode, Bencode)> = values.iter().collect();
+        entries.sort_by(|a, b| sort_key(a).cmp(sort_key(b)));
+        if entries.windows(2).any(|pair| sort_key(pair[0]) == sort_key(pair[1])) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "duplicate dictionary key"));
+        }
+        for (key, value) in entries.into_iter().rev() {
+            self.stack.push(Frame::Value(value));
+            self.stack.push(Frame::Value(key));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = io::Result<Token<'a>>;
+
+    fn next(&mut self) -> Option<io::Result<Token<'a>>> {
+        let frame = self.stack.pop()?;
+        let value = match frame {
+            Frame::End => return Some(Ok(Token::Byte(b'e'))),
+            Frame::Value(value) => value,
+        };
+        let token = match value {
+            Bencode::String(value) => Token::String(value.as_bytes()),
+            Bencode::Bytes(value) => Token::String(value),
+            Bencode::Integer(value) => Token::Integer(*value),
+            Bencode::List(values) => {
+                self.stack.push(Frame::End);
+                self.stack.extend(values.iter().rev().map(Frame::Value));
+                Token::Byte(b'l')
+            },
+            Bencode::Dictionary(values) => {
+                if let Err(err) = self.push_entries(values) {
+                    return Some(Err(err));
+                }
+                Token::Byte(b'd')
+            },
+        };
+        Some(Ok(token))
+    }
+}
+
+/// Only string keys can be encoded.
+fn key_bytes(key: &Bencode) -> io::Result<&[u8]> {
+    match key {
+        Bencode::String(key) => Ok(key.as_bytes()),
+        Bencode::Bytes(key) => Ok(key),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "dictionary key is not a string")),
+    }
+}
+
+/// Key bytes of an entry whose key was checked by [`key_bytes`].
+fn sort_key(entry: &(Bencode, Bencode)) -> &[u8] {
+    key_bytes(&entry.0).unwrap_or_default()
+}
+
+/// Formats an integer or a length prefix into `buffer`, the async writer
+/// has no `write!` and should not allocate a `String` per token.
+fn format_into<'b>(buffer: &'b mut [u8; 24], args: std::fmt::Arguments<'_>) -> io::Result<&'b [u8]> {
+    let mut cursor = &mut buffer[..];
+    cursor.write_fmt(args)?;
+    let len = 24 - cursor.len();
+    Ok(&buffer[..len])
+}
+
+impl Bencode {
+    /// Canonical encoding: dictionaries sorted by raw key bytes, string
+    /// lengths counted in bytes.
+    pub fn to_bencode_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut collector = Vec::new();
+        self.write_to(&mut collector)?;
+        Ok(collector)
+    }
+
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        for token in Tokens::new(self) {
+            match token? {
+                Token::Byte(byte) => writer.write_all(&[byte])?,
+                Token::Integer(value) => write!(writer, "i{value}e")?,
+                Token::String(value) => {
+                    write!(writer, "{}:", value.len())?;
+                    writer.write_all(value)?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as [`Bencode::write_to`], wrap unbuffered writers in a
+    /// `tokio::io::BufWriter`.
+    pub async fn write_to_async<W: AsyncWrite + Unpin + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        let mut buffer = [0; 24];
+        for token in Tokens::new(self) {
+            match token? {
+                Token::Byte(byte) => writer.write_all(&[byte]).await?,
+                Token::Integer(value) => {
+                    let digits = format_into(&mut buffer, format_args!("i{value}e"))?;
+                    writer.write_all(digits).await?;
+                },
+                Token::String(value) => {
+                    let prefix = format_into(&mut buffer, format_args!("{}:", value.len()))?;
+                    writer.write_all(prefix).await?;
+                    writer.write_all(value).await?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_encode_canonical() {
+    let value = Bencode::Dictionary(vec![
+        (Bencode::String("zeta".to_string()), Bencode::Integer(-3)),
+        (Bencode::String("name".to_string()), Bencode::String("файл".to_string())),
+        (Bencode::Bytes(vec![0xff]), Bencode::List(vec![Bencode::Bytes(vec![0, 1])])),
+        (Bencode::String("A".to_string()), Bencode::Dictionary(vec![])),
+    ]);
+    let encoded = value.to_bencode_bytes().unwrap();
+    assert_eq!(
+        encoded,
+        b"d1:Ade4:name8:\xd1\x84\xd0\xb0\xd0\xb9\xd0\xbb4:zetai-3e1:\xffl2:\x00\x01ee".to_vec()
+    );
+
+    let invalid = Bencode::Dictionary(vec![(Bencode::Integer(1), Bencode::Integer(2))]);
+    assert_eq!(invalid.to_bencode_bytes().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    let duplicate = Bencode::Dictionary(vec![
+        (Bencode::String("b".to_string()), Bencode::Integer(1)),
+        (Bencode::String("a".to_string()), Bencode::Integer(2)),
+        (Bencode::Bytes(b"b".to_vec()), Bencode::Integer(3)),
+    ]);
+    assert_eq!(duplicate.to_bencode_bytes().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    let sorted_duplicate = Bencode::Dictionary(vec![
+        (Bencode::String("a".to_string()), Bencode::Integer(1)),
+        (Bencode::String("a".to_string()), Bencode::Integer(2)),
+    ]);
+    assert!(sorted_duplicate.to_bencode_bytes().is_err());
+}
+
+#[tokio::test]
+async fn test_encode_async_matches_blocking() {
+    let decoded = crate::read_torrent_from_file("test2.torrent").await.unwrap();
+    let mut written = Vec::new();
+    decoded.write_to_async(&mut written).await.unwrap();
+    assert_eq!(written, decoded.to_bencode_bytes().unwrap());
+    assert_eq!(written, tokio::fs::read("test2.torrent").await.unwrap());
+
+    let extreme = Bencode::List(vec![Bencode::Integer(i64::MIN), Bencode::Bytes(vec![7; 1000])]);
+    let mut written = Vec::new();
+    extreme.write_to_async(&mut written).await.unwrap();
+    assert_eq!(written, extreme.to_bencode_bytes().unwrap());
+}
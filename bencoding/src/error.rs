@@ -0,0 +1,130 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    UnexpectedEof,
+    /// Byte that can't start a bencode value.
+    UnexpectedByte(u8),
+    InvalidInteger,
+    LeadingZeros,
+    NegativeZero,
+    InvalidStringLength,
+    UnterminatedList,
+    UnterminatedDictionary,
+    NonStringKey,
+    UnsortedKeys,
+    DuplicateKey,
+    TrailingData,
+    DepthLimitExceeded,
+    LengthLimitExceeded,
+}
+
+/// Reason a buffer was rejected together with the byte offset it happened at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub offset: usize,
+    pub kind: DecodeErrorKind,
+}
+
+impl DecodeError {
+    pub fn new(offset: usize, kind: DecodeErrorKind) -> Self {
+        DecodeError { offset, kind }
+    }
+}
+
+impl fmt::Display for DecodeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeErrorKind::UnexpectedEof => write!(f, "unexpected end of data"),
+            DecodeErrorKind::UnexpectedByte(byte) => write!(f, "unexpected byte 0x{byte:02x}"),
+            DecodeErrorKind::InvalidInteger => write!(f, "invalid integer"),
+            DecodeErrorKind::LeadingZeros => write!(f, "number has leading zeros"),
+            DecodeErrorKind::NegativeZero => write!(f, "negative zero is not allowed"),
+            DecodeErrorKind::InvalidStringLength => write!(f, "invalid string length"),
+            DecodeErrorKind::UnterminatedList => write!(f, "list is not terminated"),
+            DecodeErrorKind::UnterminatedDictionary => write!(f, "dictionary is not terminated"),
+            DecodeErrorKind::NonStringKey => write!(f, "dictionary key is not a string"),
+            DecodeErrorKind::UnsortedKeys => write!(f, "dictionary keys are not sorted"),
+            DecodeErrorKind::DuplicateKey => write!(f, "duplicate dictionary key"),
+            DecodeErrorKind::TrailingData => write!(f, "trailing data after root value"),
+            DecodeErrorKind::DepthLimitExceeded => write!(f, "nesting depth limit exceeded"),
+            DecodeErrorKind::LengthLimitExceeded => write!(f, "length limit exceeded"),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.kind, self.offset)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Error of the serde data format (`to_bytes` / `from_bytes`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerdeError {
+    /// Custom message from a `Serialize` or `Deserialize` implementation.
+    Message(String),
+    Decode(DecodeError),
+    /// Type that has no bencode representation, like floats.
+    UnsupportedType(&'static str),
+    /// Dictionary keys must be byte strings.
+    InvalidKey,
+    IntegerOutOfRange,
+}
+
+impl fmt::Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerdeError::Message(message) => f.write_str(message),
+            SerdeError::Decode(err) => write!(f, "{err}"),
+            SerdeError::UnsupportedType(name) => write!(f, "{name} can't be represented in bencode"),
+            SerdeError::InvalidKey => write!(f, "dictionary key must be a string"),
+            SerdeError::IntegerOutOfRange => write!(f, "integer does not fit into i64"),
+        }
+    }
+}
+
+impl std::error::Error for SerdeError {}
+
+impl serde::ser::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeError::Message(msg.to_string())
+    }
+}
+
+impl serde::de::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeError::Message(msg.to_string())
+    }
+}
+
+impl From<DecodeError> for SerdeError {
+    fn from(value: DecodeError) -> Self {
+        SerdeError::Decode(value)
+    }
+}
+
+/// Error of the path based mutation helpers on [`crate::Bencode`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+    /// Path does not follow the `key.key[index]` syntax.
+    Syntax { path: String, reason: &'static str },
+    /// Part of the path that does not exist.
+    NotFound(String),
+    /// Part of the path that is not the container the next segment needs.
+    WrongType { path: String, expected: &'static str },
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Syntax { path, reason } => write!(f, "invalid path `{path}`: {reason}"),
+            PathError::NotFound(path) => write!(f, "`{path}` does not exist"),
+            PathError::WrongType { path, expected } => write!(f, "`{path}` is not a {expected}"),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
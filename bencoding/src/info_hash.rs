@@ -0,0 +1,133 @@
+use std::fmt;
+
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+pub const INFO_HASH_LEN: usize = 20;
+pub const INFO_HASH_V2_LEN: usize = 32;
+
+/// SHA-1 of the raw bencoded `info` dictionary, identifies a torrent
+/// in trackers, handshakes and magnet links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InfoHash(pub [u8; INFO_HASH_LEN]);
+
+impl InfoHash {
+    /// Hashes the given bytes as they are. Callers must pass the exact
+    /// `info` span of the original file, a re-encoded dictionary may differ.
+    pub fn from_info_bytes(info: &[u8]) -> Self {
+        let mut hasher = Sha1::new();
+        hasher.update(info);
+        InfoHash(hasher.finalize().into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; INFO_HASH_LEN] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    pub fn from_hex<T: AsRef<str>>(hex: T) -> Option<Self> {
+        let hex = hex.as_ref().as_bytes();
+        if hex.len() != INFO_HASH_LEN * 2 {
+            return None;
+        }
+        let mut result = [0u8; INFO_HASH_LEN];
+        for (i, pair) in hex.chunks_exact(2).enumerate() {
+            result[i] = (hex_value(pair[0])? << 4) | hex_value(pair[1])?;
+        }
+        Some(InfoHash(result))
+    }
+
+    /// Percent-encodes every byte that is not unreserved (RFC 3986),
+    /// as expected by HTTP trackers in the `info_hash` query parameter.
+    pub fn url_encode(&self) -> String {
+        url_encode_bytes(&self.0)
+    }
+}
+
+impl fmt::Display for InfoHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl From<[u8; INFO_HASH_LEN]> for InfoHash {
+    fn from(value: [u8; INFO_HASH_LEN]) -> Self {
+        InfoHash(value)
+    }
+}
+
+/// SHA-256 of the `info` dictionary of a v2 or hybrid torrent (BEP 52).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InfoHashV2(pub [u8; INFO_HASH_V2_LEN]);
+
+impl InfoHashV2 {
+    /// Same contract as [`InfoHash::from_info_bytes`].
+    pub fn from_info_bytes(info: &[u8]) -> Self {
+        InfoHashV2(Sha256::digest(info).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; INFO_HASH_V2_LEN] {
+        &self.0
+    }
+
+    /// First 20 bytes, the form trackers, the DHT and the handshake use
+    /// for v2 swarms.
+    pub fn truncated(&self) -> InfoHash {
+        InfoHash(self.0[..INFO_HASH_LEN].try_into().unwrap())
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+}
+
+impl fmt::Display for InfoHashV2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+pub fn url_encode_bytes(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 3);
+    for &byte in bytes {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn hex_value(ch: u8) -> Option<u8> {
+    match ch {
+        b'0'..=b'9' => Some(ch - b'0'),
+        b'a'..=b'f' => Some(ch - b'a' + 10),
+        b'A'..=b'F' => Some(ch - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_info_hash_helpers() {
+    let hash = InfoHash::from_info_bytes(b"d4:name1:ae");
+    let hex = hash.to_hex();
+    assert_eq!(hex.len(), 40);
+    assert_eq!(InfoHash::from_hex(&hex), Some(hash));
+    assert_eq!(InfoHash::from_hex(hex.to_uppercase()), Some(hash));
+    assert_eq!(InfoHash::from_hex("zz"), None);
+
+    let mut raw = [b'a'; INFO_HASH_LEN];
+    raw[0] = 0x12;
+    raw[1] = b' ';
+    raw[2] = b'~';
+    assert_eq!(InfoHash(raw).url_encode(), format!("%12%20~{}", "a".repeat(17)));
+
+    let v2 = InfoHashV2::from_info_bytes(b"d4:name1:ae");
+    assert_eq!(v2.truncated().as_bytes()[..], v2.as_bytes()[..INFO_HASH_LEN]);
+    assert_eq!(v2.to_hex().len(), 64);
+}
@@ -0,0 +1,136 @@
+//! Reversible JSON representation of [`Bencode`].
+//!
+//! * integers are JSON numbers, lists are arrays;
+//! * UTF-8 strings are JSON strings;
+//! * binary strings are `{"$hex": "<lowercase hex>"}`;
+//! * dictionaries are objects. A UTF-8 key starting with `$` gets one more
+//!   `$` in front, a binary key is written as `"$hex:<hex>"`, so the tag
+//!   object above can never clash with a real dictionary.
+//!
+//! Duplicate keys, which only the lenient decoder accepts, keep the last
+//! value.
+
+use serde_json::{Map, Number, Value};
+
+use crate::Bencode;
+
+const BYTES_TAG: &str = "$hex";
+const BYTES_KEY_PREFIX: &str = "$hex:";
+
+pub fn to_json_value(value: &Bencode) -> Value {
+    match value {
+        Bencode::String(value) => Value::String(value.clone()),
+        Bencode::Integer(value) => Value::Number(Number::from(*value)),
+        Bencode::Bytes(value) => bytes_to_json(value),
+        Bencode::List(list) => Value::Array(list.iter().map(to_json_value).collect()),
+        Bencode::Dictionary(dict) => {
+            let mut map = Map::new();
+            for (key, value) in dict {
+                map.insert(key_to_json(key), to_json_value(value));
+            }
+            Value::Object(map)
+        },
+    }
+}
+
+pub fn from_json_value(value: &Value) -> Result<Bencode, String> {
+    match value {
+        Value::String(value) => Ok(Bencode::String(value.clone())),
+        Value::Number(number) => match number.as_i64() {
+            Some(number) => Ok(Bencode::Integer(number)),
+            None => Err(format!("{number} is not a 64 bit integer")),
+        },
+        Value::Bool(value) => Ok(Bencode::Integer(*value as i64)),
+        Value::Array(list) => list.iter().map(from_json_value).collect::<Result<_, _>>().map(Bencode::List),
+        Value::Object(map) => {
+            if let (1, Some(Value::String(hex))) = (map.len(), map.get(BYTES_TAG)) {
+                return from_hex(hex).map(Bencode::Bytes);
+            }
+            let mut dictionary = Vec::with_capacity(map.len());
+            for (key, value) in map {
+                dictionary.push((key_from_json(key)?, from_json_value(value)?));
+            }
+            Ok(Bencode::Dictionary(dictionary))
+        },
+        Value::Null => Err("null has no bencode representation".to_string()),
+    }
+}
+
+fn bytes_to_json(value: &[u8]) -> Value {
+    let mut map = Map::new();
+    map.insert(BYTES_TAG.to_string(), Value::String(to_hex(value)));
+    Value::Object(map)
+}
+
+fn key_to_json(key: &Bencode) -> String {
+    match key {
+        Bencode::String(key) if key.starts_with('$') => format!("${key}"),
+        Bencode::String(key) => key.clone(),
+        Bencode::Bytes(key) => format!("{BYTES_KEY_PREFIX}{}", to_hex(key)),
+        // Not valid bencode, but must not panic: keep the JSON of the key.
+        other => format!("$json:{}", to_json_value(other)),
+    }
+}
+
+fn key_from_json(key: &str) -> Result<Bencode, String> {
+    if let Some(escaped) = key.strip_prefix("$$") {
+        return Ok(Bencode::String(format!("${escaped}")));
+    }
+    if let Some(hex) = key.strip_prefix(BYTES_KEY_PREFIX) {
+        return from_hex(hex).map(Bencode::Bytes);
+    }
+    if key.starts_with('$') {
+        return Err(format!("unknown tagged key `{key}`"));
+    }
+    Ok(Bencode::String(key.to_string()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(format!("`{hex}` is not a hex string"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| format!("`{hex}` is not a hex string")))
+        .collect()
+}
+
+impl Bencode {
+    pub fn from_json(value: &Value) -> Result<Bencode, String> {
+        from_json_value(value)
+    }
+}
+
+#[test]
+fn test_json_round_trip() {
+    let value = Bencode::Dictionary(vec![
+        (Bencode::String("$hex".to_string()), Bencode::String("not bytes".to_string())),
+        (Bencode::Bytes(vec![0xff, 0x00]), Bencode::Integer(-1)),
+        (
+            Bencode::String("pieces".to_string()),
+            Bencode::List(vec![Bencode::Bytes(vec![0xde, 0xad]), Bencode::Dictionary(vec![])]),
+        ),
+    ]);
+    let json = to_json_value(&value);
+    assert_eq!(
+        json.to_string(),
+        r#"{"$$hex":"not bytes","$hex:ff00":-1,"pieces":[{"$hex":"dead"},{}]}"#
+    );
+    let back = Bencode::from_json(&json).unwrap();
+    assert_eq!(back.to_bencode_bytes().unwrap(), value.to_bencode_bytes().unwrap());
+
+    assert!(Bencode::from_json(&serde_json::json!({"$other": 1})).is_err());
+    assert!(Bencode::from_json(&serde_json::json!(1.5)).is_err());
+}
+
+#[tokio::test]
+async fn test_json_round_trip_torrent() {
+    let data = tokio::fs::read("test2.torrent").await.unwrap();
+    let decoded = crate::decode_bencode(&data).unwrap();
+    let json: Value = serde_json::from_str(&decoded.to_string()).unwrap();
+    assert_eq!(Bencode::from_json(&json).unwrap().to_bencode_bytes().unwrap(), data);
+}
@@ -1,16 +1,39 @@
-use std::collections::HashMap;
+#[cfg(test)]
 use std::iter::zip;
-use std::str::FromStr;
-use serde;
-use serde::{Serialize, ser::{SerializeMap}};
-use serde_json::{json, Value};
+use std::ops::Range;
+use serde::{Deserialize, Serialize, ser::{SerializeMap}, de::Visitor};
+use serde_json::Value;
+#[cfg(test)]
 use tokio::fs::File;
+#[cfg(test)]
 use tokio::io::{AsyncWriteExt, AsyncReadExt};
 
 mod read_torrent_data;
+pub mod metainfo;
+pub mod info_hash;
+pub mod error;
+pub mod options;
+pub mod borrowed;
+pub mod encode;
+pub mod ser;
+pub mod de;
+pub mod json;
+pub mod stream;
+pub mod path;
+pub mod create;
+pub mod merkle;
 
+pub use error::{DecodeError, DecodeErrorKind, PathError, SerdeError};
+pub use ser::{to_bencode, to_bytes, to_writer};
+pub use de::{from_bencode, from_bytes, from_bytes_with_options};
+pub use options::DecodeOptions;
+pub use borrowed::BencodeRef;
+pub use info_hash::{InfoHash, InfoHashV2};
+pub use create::{CreateError, CreatedTorrent, TorrentCreator};
+pub use metainfo::{FileEntry, FileLayout, FileV2, InfoDict, MetaVersion, Metainfo, MetainfoError};
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Bencode {
     String(String),
     Integer(i64),
@@ -19,61 +42,6 @@ pub enum Bencode {
     Bytes(Vec<u8>)
 }
 
-impl Bencode {
-    fn to_bencode_bytes(&self) -> Vec<u8> {
-        let mut collector: Vec<u8> = Vec::new();
-        match self {
-            Bencode::String(value) => {
-                collector.extend((value.chars().count().to_string() + ":" + value).as_bytes());
-                //old_collector.push_str((value.chars().count().to_string() + ":" + value).as_str());
-                //return old_collector;
-            },
-            Bencode::Integer(value) => {
-                collector.push(b'i');
-                collector.extend(value.to_string().as_bytes());
-                collector.push(b'e');
-
-                // old_collector.push_str("i");
-                // old_collector.push_str(value.to_string().as_str());
-                // old_collector.push('e');
-                //return old_collector;
-            },
-            Bencode::List(values) => {
-                collector.push(b'l');
-                for value in values {
-                    collector.extend(value.to_bencode_bytes());
-                }
-                collector.push(b'e');
-            },
-            Bencode::Dictionary(values) => {
-                collector.push(b'd');
-                //let mut values: Vec<(Bencode, Bencode)> = Vec::new();
-                //values.extend_from_slice(&old_values); 
-                //values.sort_by(|a,b|a.0.to_string().cmp(&b.0.to_string()));
-               
-                // TODO: BITTORRENT SPECS SAYS, THAT DICTS MUST BE SORTED LEXICOGRAPHICAL. THEORETICALLY, ALL OF TORRENT FILES MUST BE ALREADY SORTED, 
-                //BUT IN FACT, HERE MUST BE ADDED SORTING. PROBLEM IS VALUES ARE IMMUTABLE AND UNCLONABLE
-                for (key, value) in values {
-                    collector.extend(key.to_bencode_bytes());
-                    collector.extend(value.to_bencode_bytes());
-                }
-                collector.push(b'e')
-            },
-            Bencode::Bytes(value) => {
-                collector.extend(value.len().to_string().as_bytes());
-                collector.push(b':');
-                collector.extend(value);
-                //return old_collector;
-            },
-        }
-        return collector;
-    }
-    fn try_get_info_hash(&self) {
-
-    }
-}
-
-
 impl Serialize for Bencode {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -82,43 +50,89 @@ impl Serialize for Bencode {
         match self {
             Bencode::String(s) => serializer.serialize_str(s),
             Bencode::Integer(i) => serializer.serialize_i64(*i),
-            Bencode::List(list) => {
-                let serialized_list: Vec<Value> = list
-                    .iter()
-                    .map(|item| serde_json::to_value(item).unwrap())
-                    .collect();
-                serializer.collect_seq(serialized_list.into_iter())
-            }
+            Bencode::List(list) => serializer.collect_seq(list),
             Bencode::Dictionary(dict) => {
-                let serialized_dict: HashMap<String, Value> = dict
-                    .iter()
-                    .map(|(key, value)| {
-                        let key_str = serde_json::to_value(key).unwrap().as_str().unwrap().to_string();
-                        let value_json = serde_json::to_value(value).unwrap();
-                        (key_str, value_json)
-                    })
-                    .collect();
-                let mut map_serializer = serializer.serialize_map(Some(serialized_dict.len()))?;
-                for (key, value) in serialized_dict.clone() {
-                    map_serializer.serialize_entry(&key, &value)?;
+                let mut map_serializer = serializer.serialize_map(Some(dict.len()))?;
+                for (key, value) in dict {
+                    map_serializer.serialize_entry(key, value)?;
                 }
-                let res = map_serializer.end()?;
-                Ok(res)
+                map_serializer.end()
             }
             Bencode::Bytes(bytes) => {
-                serializer.serialize_bytes(&bytes)
+                serializer.serialize_bytes(bytes)
             },
         }
     }
 }
-impl ToString for Bencode {
-    fn to_string(&self) -> String {
+
+impl<'de> Deserialize<'de> for Bencode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct BencodeVisitor;
+
+        impl<'de> Visitor<'de> for BencodeVisitor {
+            type Value = Bencode;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a bencode value")
+            }
+
+            fn visit_bool<E: serde::de::Error>(self, v: bool) -> Result<Bencode, E> {
+                Ok(Bencode::Integer(v as i64))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Bencode, E> {
+                Ok(Bencode::Integer(v))
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Bencode, E> {
+                i64::try_from(v)
+                    .map(Bencode::Integer)
+                    .map_err(|_| E::custom("integer does not fit into i64"))
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Bencode, E> {
+                Ok(Bencode::String(v.to_string()))
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Bencode, E> {
+                match std::str::from_utf8(v) {
+                    Ok(v) => Ok(Bencode::String(v.to_string())),
+                    Err(_) => Ok(Bencode::Bytes(v.to_vec())),
+                }
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Bencode, A::Error> {
+                let mut list = Vec::new();
+                while let Some(value) = seq.next_element()? {
+                    list.push(value);
+                }
+                Ok(Bencode::List(list))
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Bencode, A::Error> {
+                let mut dictionary = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    dictionary.push(entry);
+                }
+                Ok(Bencode::Dictionary(dictionary))
+            }
+        }
+
+        deserializer.deserialize_any(BencodeVisitor)
+    }
+}
+
+/// Strings are printed as they are, everything else in the lossless JSON
+/// form of the `json` module.
+impl std::fmt::Display for Bencode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Bencode::String(value) => return value.clone(),
-            Bencode::Integer(value) => value.to_string(),
-            Bencode::List(list) => json!(list).to_string(),
-            Bencode::Dictionary(_) => todo!(),
-            Bencode::Bytes(_) => todo!(),
+            Bencode::String(value) => f.write_str(value),
+            Bencode::Integer(value) => write!(f, "{value}"),
+            other => write!(f, "{}", json::to_json_value(other)),
         }
     }
 }
@@ -126,116 +140,262 @@ impl ToString for Bencode {
 pub async fn read_torrent_from_file<T: AsRef<str>>(filename: T) -> Result<Bencode, String>{
     let content = tokio::fs::read(filename.as_ref()).await;
     match content {
-        Ok(res) => {
-            let data = decode_bencode(&res);
-            match data {
-                Some(value) => Ok(value),
-                None => Err("Some uknown error. File has incorrect data.".to_string()),
-            }
-        },
+        Ok(res) => decode_bencode(&res).map_err(|err| format!("File has incorrect data: {err}")),
         Err(err) => {
             Err(err.to_string())
         },
     }
 }
 
-pub fn decode_bencode(data: &[u8]) -> Option<Bencode> {
-    let mut iterator = data.iter();
-    decode_value(&mut iterator)
+pub async fn read_metainfo_from_file<T: AsRef<str>>(filename: T) -> Result<Metainfo, String> {
+    match tokio::fs::read(filename.as_ref()).await {
+        Ok(content) => Metainfo::from_bytes(&content).map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    }
 }
 
-fn decode_value(iterator: &mut std::slice::Iter<u8>) -> Option<Bencode> {
-    let mut length = String::new();
-    match iterator.next()? {
-        b'd' => decode_dictionary(iterator),
-        b'l' => decode_list(iterator),
-        b'i' => decode_integer(iterator),
-        ch @ b'0'..=b'9' => {
-            length.push(*ch as char);
-            decode_string(iterator, &mut length)
-        },
-        _ => None
-    }
+/// Position of the decoder inside the input, used for error offsets.
+struct Cursor<'a, 'o> {
+    data: &'a [u8],
+    pos: usize,
+    depth: usize,
+    options: &'o DecodeOptions,
+    /// `data` was cut down to `options.max_length`.
+    truncated: bool,
 }
 
-fn decode_dictionary(iterator: &mut std::slice::Iter<u8>) -> Option<Bencode> {
-    let mut dictionary = Vec::new();
-    let mut x = 0;
-    while let Some(key) = decode_string(iterator, &mut String::new()) {
-        //println!("{:?}", &key);
-        x+=1;
-        let value = decode_value(iterator)?;
-        dictionary.push((key, value));
+impl<'a, 'o> Cursor<'a, 'o> {
+    fn new(data: &'a [u8], options: &'o DecodeOptions) -> Self {
+        let truncated = data.len() > options.max_length;
+        let data = if truncated { &data[..options.max_length] } else { data };
+        Cursor { data, pos: 0, depth: 0, options, truncated }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn error(&self, kind: DecodeErrorKind) -> DecodeError {
+        self.error_at(self.pos, kind)
+    }
+
+    fn error_at(&self, offset: usize, kind: DecodeErrorKind) -> DecodeError {
+        let hit_end = offset >= self.data.len();
+        let kind = match kind {
+            DecodeErrorKind::UnexpectedEof
+            | DecodeErrorKind::UnterminatedList
+            | DecodeErrorKind::UnterminatedDictionary
+                if self.truncated && hit_end => DecodeErrorKind::LengthLimitExceeded,
+            kind => kind,
+        };
+        DecodeError::new(offset, kind)
+    }
+
+    fn enter(&mut self) -> Result<(), DecodeError> {
+        self.depth += 1;
+        if self.depth > self.options.max_depth {
+            return Err(self.error(DecodeErrorKind::DepthLimitExceeded));
+        }
+        Ok(())
+    }
+
+    fn finish(&self, full_length: usize) -> Result<(), DecodeError> {
+        if self.options.strict && self.pos != full_length {
+            return Err(self.error(DecodeErrorKind::TrailingData));
+        }
+        Ok(())
     }
-    //println!("while ended at {:?}", x);
-    Some(Bencode::Dictionary(dictionary))
 }
 
-fn decode_list(iterator: &mut std::slice::Iter<u8>) -> Option<Bencode> {
-    let mut list = Vec::new();
+/// Decodes with the default, lenient options.
+pub fn decode_bencode(data: &[u8]) -> Result<Bencode, DecodeError> {
+    decode_bencode_with_options(data, &DecodeOptions::default())
+}
+
+pub fn decode_bencode_with_options(data: &[u8], options: &DecodeOptions) -> Result<Bencode, DecodeError> {
+    decode_bencode_ref_with_options(data, options).map(BencodeRef::into_owned)
+}
+
+/// Zero-copy decoding, strings of the result borrow from `data`.
+pub fn decode_bencode_ref(data: &[u8]) -> Result<BencodeRef<'_>, DecodeError> {
+    decode_bencode_ref_with_options(data, &DecodeOptions::default())
+}
 
-    while let Some(value) = decode_value(iterator) {
-        list.push(value);
+pub fn decode_bencode_ref_with_options<'a>(data: &'a [u8], options: &DecodeOptions) -> Result<BencodeRef<'a>, DecodeError> {
+    let mut cursor = Cursor::new(data, options);
+    let value = decode_value(&mut cursor)?;
+    cursor.finish(data.len())?;
+    Ok(value)
+}
+
+/// Decodes a torrent file and also returns the byte range of the top level
+/// `info` value inside `data`, which is what the info-hash is computed over.
+pub fn decode_bencode_with_info_span(data: &[u8], options: &DecodeOptions) -> Result<(Bencode, Option<Range<usize>>), DecodeError> {
+    let mut cursor = Cursor::new(data, options);
+    if cursor.peek() != Some(b'd') {
+        return Ok((decode_bencode_with_options(data, options)?, None));
     }
+    cursor.next();
+    cursor.enter()?;
+    let mut info_span = None;
+    let value = decode_dictionary_entries(&mut cursor, |key, span| {
+        if key == b"info" {
+            info_span = Some(span);
+        }
+    })?;
+    cursor.finish(data.len())?;
+    Ok((value.into_owned(), info_span))
+}
 
-    Some(Bencode::List(list))
+fn decode_value<'a>(cursor: &mut Cursor<'a, '_>) -> Result<BencodeRef<'a>, DecodeError> {
+    match cursor.peek() {
+        Some(b'd') => {
+            cursor.next();
+            decode_dictionary(cursor)
+        },
+        Some(b'l') => {
+            cursor.next();
+            decode_list(cursor)
+        },
+        Some(b'i') => {
+            cursor.next();
+            decode_integer(cursor)
+        },
+        Some(b'0'..=b'9') => decode_string(cursor),
+        Some(byte) => Err(cursor.error(DecodeErrorKind::UnexpectedByte(byte))),
+        None => Err(cursor.error(DecodeErrorKind::UnexpectedEof)),
+    }
 }
 
-fn decode_integer(iterator: &mut std::slice::Iter<u8>) -> Option<Bencode> {
-    let mut integer = String::new();
+fn decode_dictionary<'a>(cursor: &mut Cursor<'a, '_>) -> Result<BencodeRef<'a>, DecodeError> {
+    cursor.enter()?;
+    decode_dictionary_entries(cursor, |_, _| {})
+}
 
-    while let Some(&byte) = iterator.next() {
-        match byte {
-            b'e' => {
-                if let Ok(value) = integer.parse::<i64>() {
-                    return Some(Bencode::Integer(value));
-                } else {
-                    break;
-                }
+/// Decodes dictionary entries after the leading `d`, calling `on_entry` with
+/// the raw key and the byte span of every value.
+fn decode_dictionary_entries<'a, F>(cursor: &mut Cursor<'a, '_>, mut on_entry: F) -> Result<BencodeRef<'a>, DecodeError>
+where
+    F: FnMut(&[u8], Range<usize>),
+{
+    let mut dictionary: Vec<(BencodeRef, BencodeRef)> = Vec::new();
+    loop {
+        match cursor.peek() {
+            Some(b'e') => {
+                cursor.next();
+                cursor.depth -= 1;
+                return Ok(BencodeRef::Dictionary(dictionary));
+            },
+            Some(b'0'..=b'9') => {},
+            Some(_) => return Err(cursor.error(DecodeErrorKind::NonStringKey)),
+            None => return Err(cursor.error(DecodeErrorKind::UnterminatedDictionary)),
+        }
+        let key_start = cursor.pos;
+        let key = decode_string(cursor)?;
+        let raw_key = key.as_raw_bytes().unwrap_or_default();
+        if let (true, Some((previous, _))) = (cursor.options.strict, dictionary.last()) {
+            match previous.as_raw_bytes().unwrap_or_default().cmp(raw_key) {
+                std::cmp::Ordering::Less => {},
+                std::cmp::Ordering::Equal => {
+                    return Err(DecodeError::new(key_start, DecodeErrorKind::DuplicateKey))
+                },
+                std::cmp::Ordering::Greater => {
+                    return Err(DecodeError::new(key_start, DecodeErrorKind::UnsortedKeys))
+                },
             }
-            b'0'..=b'9' | b'-' => integer.push(byte as char),
-            _ => break,
         }
+        let value_start = cursor.pos;
+        let value = decode_value(cursor)?;
+        on_entry(raw_key, value_start..cursor.pos);
+        dictionary.push((key, value));
     }
+}
 
-    None
+fn decode_list<'a>(cursor: &mut Cursor<'a, '_>) -> Result<BencodeRef<'a>, DecodeError> {
+    cursor.enter()?;
+    let mut list = Vec::new();
+    loop {
+        match cursor.peek() {
+            Some(b'e') => {
+                cursor.next();
+                cursor.depth -= 1;
+                return Ok(BencodeRef::List(list));
+            },
+            Some(_) => list.push(decode_value(cursor)?),
+            None => return Err(cursor.error(DecodeErrorKind::UnterminatedList)),
+        }
+    }
 }
 
-fn decode_string(iterator: &mut std::slice::Iter<u8>, length: &mut String) -> Option<Bencode> {
-    //let mut length = String::new();
-
-    while let Some(&byte) = iterator.next() {
-        match byte {
-            b':' => {
-                //println!("GOT DATA: {:?} {:?} ", length, byte);
-                if let Ok(len) = length.parse::<usize>() {
-                    let mut string = Vec::with_capacity(len);
-                    for _ in 0..len {
-                        if let Some(&byte) = iterator.next() {
-                            string.push(byte);
-                        } else {
-                            return None;
-                        }
-                    }
-                    if String::from_utf8(string.clone()).is_err(){
-                        return Some(Bencode::Bytes(string.clone()));
-                    }
-                    return Some(Bencode::String(String::from_utf8_lossy(&string).into_owned()));
-                } else {
-                    //println!("ERRRR");
-                    break;
-                }
-            }
-            b'0'..=b'9' => length.push(byte as char),
-            _ => break,
+fn decode_integer<'a>(cursor: &mut Cursor<'a, '_>) -> Result<BencodeRef<'a>, DecodeError> {
+    let start = cursor.pos;
+    loop {
+        match cursor.next() {
+            Some(b'e') => break,
+            Some(b'0'..=b'9' | b'-') => {},
+            Some(_) => return Err(DecodeError::new(cursor.pos - 1, DecodeErrorKind::InvalidInteger)),
+            None => return Err(cursor.error(DecodeErrorKind::UnexpectedEof)),
         }
     }
+    let digits = &cursor.data[start..cursor.pos - 1];
+    let invalid = DecodeError::new(start, DecodeErrorKind::InvalidInteger);
+    let unsigned = digits.strip_prefix(b"-").unwrap_or(digits);
+    if unsigned.is_empty() || unsigned.contains(&b'-') {
+        return Err(invalid);
+    }
+    if cursor.options.strict {
+        if unsigned == b"0" && digits.len() == 2 {
+            return Err(DecodeError::new(start, DecodeErrorKind::NegativeZero));
+        }
+        if unsigned.len() > 1 && unsigned[0] == b'0' {
+            return Err(DecodeError::new(start, DecodeErrorKind::LeadingZeros));
+        }
+    }
+    // Only ASCII digits and a sign are left here.
+    let text = std::str::from_utf8(digits).map_err(|_| invalid.clone())?;
+    text.parse::<i64>().map(BencodeRef::Integer).map_err(|_| invalid)
+}
 
-    None
+fn decode_string<'a>(cursor: &mut Cursor<'a, '_>) -> Result<BencodeRef<'a>, DecodeError> {
+    let start = cursor.pos;
+    loop {
+        match cursor.next() {
+            Some(b':') => break,
+            Some(b'0'..=b'9') => {},
+            Some(_) => return Err(DecodeError::new(cursor.pos - 1, DecodeErrorKind::InvalidStringLength)),
+            None => return Err(cursor.error(DecodeErrorKind::UnexpectedEof)),
+        }
+    }
+    let digits = &cursor.data[start..cursor.pos - 1];
+    if digits.is_empty() {
+        return Err(DecodeError::new(start, DecodeErrorKind::InvalidStringLength));
+    }
+    if cursor.options.strict && digits.len() > 1 && digits[0] == b'0' {
+        return Err(DecodeError::new(start, DecodeErrorKind::LeadingZeros));
+    }
+    let len = std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse::<usize>().ok())
+        .ok_or_else(|| DecodeError::new(start, DecodeErrorKind::InvalidStringLength))?;
+    let end = cursor.pos.checked_add(len).filter(|end| *end <= cursor.data.len());
+    let end = end.ok_or_else(|| cursor.error_at(cursor.data.len(), DecodeErrorKind::UnexpectedEof))?;
+    let string = &cursor.data[cursor.pos..end];
+    cursor.pos = end;
+    match std::str::from_utf8(string) {
+        Ok(string) => Ok(BencodeRef::String(string)),
+        Err(_) => Ok(BencodeRef::Bytes(string)),
+    }
 }
 impl Bencode {
+    /// See the `json` module for the mapping, `Bencode::from_json` reverses it.
     pub async fn to_json(&self) -> Value {
-        return json!(self);
+        json::to_json_value(self)
     }
 }
 #[tokio::test]
@@ -244,11 +404,11 @@ async fn test_decode_from_file_and_write_json() {
     
     if let Some(decoded) = read_torrent_from_file("test2.torrent").await.ok() {
         let json_data = decoded.to_json().await;
-        let file_path = "data3.json";
-        let mut file = File::create(file_path).await.expect("Не удалось создать файл");
+        let file_path = std::env::temp_dir().join(format!("bencoding-data3-{}.json", std::process::id()));
+        let mut file = File::create(&file_path).await.expect("Не удалось создать файл");
         let json_string = serde_json::to_string_pretty(&json_data).expect("Ошибка сериализации в JSON");
         file.write_all(json_string.as_bytes()).await.expect("Ошибка записи в файл");
-
+        tokio::fs::remove_file(&file_path).await.unwrap();
     } else {
         println!("Error decoding Bencode");
         assert!(false);
@@ -258,7 +418,7 @@ async fn test_decode_from_file_and_write_json() {
 #[tokio::test]
 async fn test_decode_from_file_and_encode_again() {
     if let Some(decoded) = read_torrent_from_file("test2.torrent").await.ok() {
-        let encoded_data = decoded.to_bencode_bytes();
+        let encoded_data = decoded.to_bencode_bytes().unwrap();
     
         let file_path = "data2_encoded.torrent";
         let mut file = File::create(file_path).await.expect("Не удалось создать файл");
@@ -276,4 +436,54 @@ async fn test_decode_from_file_and_encode_again() {
         println!("Error decoding Bencode");
         assert!(false);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_decode_errors() {
+    let strict = DecodeOptions::strict();
+    let decode = |data: &[u8]| decode_bencode_with_options(data, &strict);
+    let error = |offset, kind| Err(DecodeError::new(offset, kind));
+    assert_eq!(decode(b"i42e"), Ok(Bencode::Integer(42)));
+    assert_eq!(decode(b"i-42e"), Ok(Bencode::Integer(-42)));
+    assert_eq!(decode(b""), error(0, DecodeErrorKind::UnexpectedEof));
+    assert_eq!(decode(b"i42"), error(3, DecodeErrorKind::UnexpectedEof));
+    assert_eq!(decode(b"i4x2e"), error(2, DecodeErrorKind::InvalidInteger));
+    assert_eq!(decode(b"i--1e"), error(1, DecodeErrorKind::InvalidInteger));
+    assert_eq!(decode(b"i03e"), error(1, DecodeErrorKind::LeadingZeros));
+    assert_eq!(decode(b"i-0e"), error(1, DecodeErrorKind::NegativeZero));
+    assert_eq!(decode(b"5:abc"), error(5, DecodeErrorKind::UnexpectedEof));
+    assert_eq!(decode(b"l1:a"), error(4, DecodeErrorKind::UnterminatedList));
+    assert_eq!(decode(b"d1:a1:b"), error(7, DecodeErrorKind::UnterminatedDictionary));
+    assert_eq!(decode(b"di1e1:ae"), error(1, DecodeErrorKind::NonStringKey));
+    assert_eq!(decode(b"d1:bi1e1:ai2ee"), error(7, DecodeErrorKind::UnsortedKeys));
+    assert_eq!(decode(b"d1:ai1e1:ai2ee"), error(7, DecodeErrorKind::DuplicateKey));
+    assert_eq!(decode(b"i1ei2e"), error(3, DecodeErrorKind::TrailingData));
+    assert_eq!(decode(b"x"), error(0, DecodeErrorKind::UnexpectedByte(b'x')));
+}
+
+#[test]
+fn test_decode_lenient_and_limits() {
+    assert_eq!(decode_bencode(b"i03e"), Ok(Bencode::Integer(3)));
+    assert_eq!(decode_bencode(b"i-0e"), Ok(Bencode::Integer(0)));
+    assert_eq!(decode_bencode(b"i1ei2e"), Ok(Bencode::Integer(1)));
+    assert_eq!(
+        decode_bencode(b"d1:bi1e1:ai2ee"),
+        Ok(Bencode::Dictionary(vec![
+            (Bencode::String("b".to_string()), Bencode::Integer(1)),
+            (Bencode::String("a".to_string()), Bencode::Integer(2)),
+        ]))
+    );
+
+    let shallow = DecodeOptions::lenient().max_depth(2);
+    assert!(decode_bencode_with_options(b"llee", &shallow).is_ok());
+    assert_eq!(
+        decode_bencode_with_options(b"llleee", &shallow),
+        Err(DecodeError::new(3, DecodeErrorKind::DepthLimitExceeded))
+    );
+    let short = DecodeOptions::lenient().max_length(4);
+    assert!(decode_bencode_with_options(b"2:ab", &short).is_ok());
+    assert_eq!(
+        decode_bencode_with_options(b"3:abc", &short),
+        Err(DecodeError::new(4, DecodeErrorKind::LengthLimitExceeded))
+    );
+}
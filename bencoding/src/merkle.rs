@@ -0,0 +1,101 @@
+//! SHA-256 hash trees of BEP 52. Every file has its own tree over 16 KiB
+//! blocks, padded with zero leaves up to a power of two.
+
+use sha2::{Digest, Sha256};
+
+pub const BLOCK_SIZE: u64 = 16 * 1024;
+pub const HASH_LEN: usize = 32;
+
+pub type Hash = [u8; HASH_LEN];
+
+pub fn hash_block(data: &[u8]) -> Hash {
+    Sha256::digest(data).into()
+}
+
+fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Root of a subtree of `leaves` zero leaves, the padding used above the
+/// leaf layer.
+pub fn zero_root(leaves: u64) -> Hash {
+    let mut hash = [0; HASH_LEN];
+    let mut width = 1;
+    while width < leaves {
+        hash = hash_pair(&hash, &hash);
+        width *= 2;
+    }
+    hash
+}
+
+/// Root over `hashes` padded with `pad` up to `width`, a power of two.
+pub fn root(hashes: &[Hash], width: usize, pad: Hash) -> Hash {
+    debug_assert!(width.is_power_of_two() && hashes.len() <= width);
+    let mut layer = hashes.to_vec();
+    layer.resize(width, pad);
+    while layer.len() > 1 {
+        layer = layer.chunks_exact(2).map(|pair| hash_pair(&pair[0], &pair[1])).collect();
+    }
+    layer[0]
+}
+
+/// Hash of one piece of a file from the hashes of its blocks. A file that
+/// fits into one piece is padded to a power of two of its own blocks only,
+/// so its piece hash is already the `pieces root`.
+pub fn piece_hash(blocks: &[Hash], file_length: u64, piece_length: u64) -> Hash {
+    let width = if file_length <= piece_length {
+        blocks.len().next_power_of_two()
+    } else {
+        (piece_length / BLOCK_SIZE) as usize
+    };
+    root(blocks, width, [0; HASH_LEN])
+}
+
+/// `pieces root` of a file larger than one piece from its piece layer.
+pub fn root_from_layer(layer: &[Hash], piece_length: u64) -> Hash {
+    root(layer, layer.len().next_power_of_two(), zero_root(piece_length / BLOCK_SIZE))
+}
+
+/// `pieces root` and piece layer of a whole file held in memory. The
+/// layer is empty for files of one piece or less, BEP 52 leaves them out
+/// of `piece layers`.
+pub fn file_hashes(data: &[u8], piece_length: u64) -> (Hash, Vec<Hash>) {
+    let layer: Vec<Hash> = data
+        .chunks(piece_length as usize)
+        .map(|piece| {
+            let blocks: Vec<Hash> = piece.chunks(BLOCK_SIZE as usize).map(hash_block).collect();
+            piece_hash(&blocks, data.len() as u64, piece_length)
+        })
+        .collect();
+    if layer.len() == 1 {
+        (layer[0], Vec::new())
+    } else {
+        (root_from_layer(&layer, piece_length), layer)
+    }
+}
+
+#[test]
+fn test_merkle_roots() {
+    let piece_length = 2 * BLOCK_SIZE;
+    let data: Vec<u8> = (0..5 * BLOCK_SIZE as usize + 100).map(|i| (i % 251) as u8).collect();
+    let blocks: Vec<Hash> = data.chunks(BLOCK_SIZE as usize).map(hash_block).collect();
+    assert_eq!(blocks.len(), 6);
+
+    // Straight from the definition: 6 leaves padded with zeros to 8.
+    let zero = [0; HASH_LEN];
+    let l1: Vec<Hash> = [&blocks[..], &[zero, zero]].concat().chunks(2).map(|p| hash_pair(&p[0], &p[1])).collect();
+    let l2: Vec<Hash> = l1.chunks(2).map(|p| hash_pair(&p[0], &p[1])).collect();
+    let expected = hash_pair(&l2[0], &l2[1]);
+
+    let (pieces_root, layer) = file_hashes(&data, piece_length);
+    assert_eq!(pieces_root, expected);
+    assert_eq!(layer, vec![l1[0], l1[1], l1[2]]);
+    // Bigger pieces give the same root, only the layer changes.
+    assert_eq!(file_hashes(&data, 4 * BLOCK_SIZE).0, expected);
+    assert_eq!(file_hashes(&data, 8 * BLOCK_SIZE), (expected, Vec::new()));
+
+    assert_eq!(file_hashes(b"abc", piece_length), (hash_block(b"abc"), Vec::new()));
+}
@@ -0,0 +1,633 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::merkle::{self, BLOCK_SIZE};
+use crate::{decode_bencode_with_info_span, Bencode, DecodeError, DecodeOptions, InfoHash, InfoHashV2};
+
+/// Length of a single SHA-1 piece hash inside the `pieces` string.
+pub const PIECE_HASH_LEN: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetainfoError {
+    /// Input is not valid bencode.
+    Decode(DecodeError),
+    /// Root of the torrent is not a dictionary at all.
+    NotADictionary,
+    MissingKey(String),
+    WrongType { key: String, expected: &'static str },
+    InvalidValue { key: String, reason: String },
+}
+
+impl fmt::Display for MetainfoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetainfoError::Decode(err) => write!(f, "torrent is not valid bencode: {err}"),
+            MetainfoError::NotADictionary => write!(f, "torrent root is not a dictionary"),
+            MetainfoError::MissingKey(key) => write!(f, "missing required key `{key}`"),
+            MetainfoError::WrongType { key, expected } => {
+                write!(f, "key `{key}` has wrong type, expected {expected}")
+            }
+            MetainfoError::InvalidValue { key, reason } => {
+                write!(f, "key `{key}` has invalid value: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MetainfoError {}
+
+/// One entry of the multi-file `files` list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    pub length: u64,
+    pub path: Vec<String>,
+    /// Padding file (BEP 47 `attr` containing `p`), zeros that are never
+    /// written to disk.
+    pub padding: bool,
+}
+
+/// File of the v2 `file tree`, in tree order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileV2 {
+    pub length: u64,
+    pub path: Vec<String>,
+    /// Merkle root of the file, absent for empty files.
+    pub pieces_root: Option<merkle::Hash>,
+}
+
+/// Which info dictionaries the torrent carries (BEP 52).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetaVersion {
+    #[default]
+    V1,
+    V2,
+    /// Both `pieces` and `file tree`, describing the same files.
+    Hybrid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileLayout {
+    /// Single-file torrent, `name` is the file name.
+    Single { length: u64 },
+    /// Multi-file torrent, `name` is the root directory.
+    Multi { files: Vec<FileEntry> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InfoDict {
+    pub name: String,
+    pub piece_length: u64,
+    /// v1 piece hashes, empty for v2-only torrents.
+    pub pieces: Vec<[u8; PIECE_HASH_LEN]>,
+    /// v1 file list, or the files of the `file tree` for v2-only torrents.
+    pub layout: FileLayout,
+    pub private: bool,
+    pub version: MetaVersion,
+    /// v2 files, empty for v1-only torrents.
+    pub files_v2: Vec<FileV2>,
+}
+
+impl InfoDict {
+    pub fn total_length(&self) -> u64 {
+        match &self.layout {
+            FileLayout::Single { length } => *length,
+            FileLayout::Multi { files } => files.iter().map(|file| file.length).sum(),
+        }
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.pieces.len()
+    }
+
+    /// Size of the piece at `index`, the last piece is usually shorter.
+    pub fn piece_size(&self, index: usize) -> Option<u64> {
+        if index >= self.pieces.len() {
+            return None;
+        }
+        let offset = index as u64 * self.piece_length;
+        Some((self.total_length() - offset).min(self.piece_length))
+    }
+
+    /// Hybrid torrents must list the same files in both versions, padding
+    /// files aside.
+    fn same_files_in_v1_and_v2(&self) -> bool {
+        let v1: Vec<(&[String], u64)> = match &self.layout {
+            FileLayout::Single { length } => vec![(std::slice::from_ref(&self.name), *length)],
+            FileLayout::Multi { files } => files
+                .iter()
+                .filter(|file| !file.padding)
+                .map(|file| (file.path.as_slice(), file.length))
+                .collect(),
+        };
+        let v2: Vec<(&[String], u64)> =
+            self.files_v2.iter().map(|file| (file.path.as_slice(), file.length)).collect();
+        v1 == v2
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metainfo {
+    pub announce: Option<String>,
+    /// Tracker tiers from `announce-list` (BEP 12), empty when absent.
+    pub announce_list: Vec<Vec<String>>,
+    /// HTTP seeds from `url-list` (BEP 19).
+    pub web_seeds: Vec<String>,
+    pub info: InfoDict,
+    /// v1 info-hash, or the truncated v2 one for v2-only torrents.
+    pub info_hash: InfoHash,
+    pub info_hash_v2: Option<InfoHashV2>,
+    /// Piece layers of the v2 files bigger than one piece, by pieces root.
+    pub piece_layers: BTreeMap<merkle::Hash, Vec<merkle::Hash>>,
+    pub creation_date: Option<i64>,
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+}
+
+impl Metainfo {
+    /// Parses a whole `.torrent` file, the info-hash is taken over the raw
+    /// `info` bytes of `data`.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MetainfoError> {
+        Metainfo::from_bytes_with_options(data, &DecodeOptions::default())
+    }
+
+    pub fn from_bytes_with_options(data: &[u8], options: &DecodeOptions) -> Result<Self, MetainfoError> {
+        let (decoded, info_span) =
+            decode_bencode_with_info_span(data, options).map_err(MetainfoError::Decode)?;
+        let info_span = match (&decoded, info_span) {
+            (Bencode::Dictionary(_), Some(span)) => span,
+            (Bencode::Dictionary(_), None) => {
+                return Err(MetainfoError::MissingKey("info".to_string()))
+            }
+            _ => return Err(MetainfoError::NotADictionary),
+        };
+        Metainfo::from_bencode(&decoded, &data[info_span])
+    }
+
+    /// Builds the typed model from an already decoded tree. The info-hashes
+    /// can't be recovered from the tree, so the raw bytes of the `info`
+    /// dictionary have to be supplied.
+    pub fn from_bencode(value: &Bencode, info_bytes: &[u8]) -> Result<Self, MetainfoError> {
+        let root = match value {
+            Bencode::Dictionary(_) => value,
+            _ => return Err(MetainfoError::NotADictionary),
+        };
+        let info = match root.get("info") {
+            Some(info) => InfoDict::from_bencode(info)?,
+            None => return Err(MetainfoError::MissingKey("info".to_string())),
+        };
+        let announce_list = match root.get("announce-list") {
+            Some(tiers) => parse_announce_list(tiers)?,
+            None => Vec::new(),
+        };
+        let web_seeds = match root.get("url-list") {
+            Some(Bencode::List(urls)) => urls.iter().filter_map(as_string).collect(),
+            Some(url) => as_string(url).into_iter().collect(),
+            None => Vec::new(),
+        };
+        let info_hash_v2 = match info.version {
+            MetaVersion::V1 => None,
+            MetaVersion::V2 | MetaVersion::Hybrid => Some(InfoHashV2::from_info_bytes(info_bytes)),
+        };
+        let info_hash = match info_hash_v2 {
+            Some(hash) if info.version == MetaVersion::V2 => hash.truncated(),
+            _ => InfoHash::from_info_bytes(info_bytes),
+        };
+        let piece_layers = match info.version {
+            MetaVersion::V1 => BTreeMap::new(),
+            MetaVersion::V2 | MetaVersion::Hybrid => parse_piece_layers(root.get("piece layers"), &info)?,
+        };
+
+        Ok(Metainfo {
+            announce: optional_string(root, "", "announce")?,
+            announce_list,
+            web_seeds,
+            info,
+            info_hash,
+            info_hash_v2,
+            piece_layers,
+            creation_date: optional_integer(root, "", "creation date")?,
+            comment: optional_string(root, "", "comment")?,
+            created_by: optional_string(root, "", "created by")?,
+        })
+    }
+
+    /// SHA-1 info-hash, `None` for v2-only torrents.
+    pub fn info_hash_v1(&self) -> Option<InfoHash> {
+        match self.info.version {
+            MetaVersion::V1 | MetaVersion::Hybrid => Some(self.info_hash),
+            MetaVersion::V2 => None,
+        }
+    }
+
+    /// Hashes to announce and handshake with: the v1 one and the truncated
+    /// v2 one, a hybrid torrent is part of both swarms.
+    pub fn swarm_hashes(&self) -> Vec<InfoHash> {
+        let mut hashes: Vec<InfoHash> = self.info_hash_v1().into_iter().collect();
+        hashes.extend(self.info_hash_v2.map(|hash| hash.truncated()));
+        hashes
+    }
+
+    /// Every tracker url of the torrent, `announce-list` tiers first.
+    pub fn trackers(&self) -> Vec<String> {
+        let mut trackers: Vec<String> = self.announce_list.iter().flatten().cloned().collect();
+        if let Some(announce) = &self.announce {
+            if !trackers.contains(announce) {
+                trackers.push(announce.clone());
+            }
+        }
+        trackers
+    }
+}
+
+impl InfoDict {
+    pub fn from_bencode(value: &Bencode) -> Result<Self, MetainfoError> {
+        let dict = match value {
+            Bencode::Dictionary(_) => value,
+            _ => return Err(wrong_type("info", "dictionary")),
+        };
+
+        let name = required_string(dict, "info.", "name")?;
+        if !is_path_element(&name) {
+            return Err(invalid("info.name", "invalid path element"));
+        }
+        let piece_length = required_integer(dict, "info.", "piece length")?;
+        if piece_length <= 0 {
+            return Err(invalid("info.piece length", "must be positive"));
+        }
+        let piece_length = piece_length as u64;
+
+        let version = match (dict.get("pieces"), optional_integer(dict, "info.", "meta version")?) {
+            (Some(_), None | Some(1)) => MetaVersion::V1,
+            (None, Some(2)) => MetaVersion::V2,
+            (Some(_), Some(2)) => MetaVersion::Hybrid,
+            (None, None | Some(1)) => return Err(MetainfoError::MissingKey("info.pieces".to_string())),
+            (_, Some(_)) => return Err(invalid("info.meta version", "only versions 1 and 2 are known")),
+        };
+
+        let files_v2 = match version {
+            MetaVersion::V1 => Vec::new(),
+            MetaVersion::V2 | MetaVersion::Hybrid => {
+                if piece_length < BLOCK_SIZE || !piece_length.is_power_of_two() {
+                    return Err(invalid("info.piece length", "must be a power of two of at least 16 KiB"));
+                }
+                match dict.get("file tree") {
+                    Some(tree) => parse_file_tree(tree)?,
+                    None => return Err(MetainfoError::MissingKey("info.file tree".to_string())),
+                }
+            }
+        };
+
+        let private = match optional_integer(dict, "info.", "private")? {
+            None | Some(0) => false,
+            Some(1) => true,
+            Some(_) => return Err(invalid("info.private", "must be 0 or 1")),
+        };
+
+        if version == MetaVersion::V2 {
+            let layout = match files_v2.as_slice() {
+                [file] if file.path == [name.as_str()] => FileLayout::Single { length: file.length },
+                files => FileLayout::Multi {
+                    files: files
+                        .iter()
+                        .map(|file| FileEntry { length: file.length, path: file.path.clone(), padding: false })
+                        .collect(),
+                },
+            };
+            return Ok(InfoDict { name, piece_length, pieces: Vec::new(), layout, private, version, files_v2 });
+        }
+
+        let pieces = match dict.get("pieces") {
+            Some(value) => value.as_bytes().ok_or_else(|| wrong_type("info.pieces", "byte string"))?,
+            None => return Err(MetainfoError::MissingKey("info.pieces".to_string())),
+        };
+        if pieces.len() % PIECE_HASH_LEN != 0 {
+            return Err(invalid("info.pieces", "length is not a multiple of 20"));
+        }
+        let pieces: Vec<[u8; PIECE_HASH_LEN]> = pieces
+            .chunks_exact(PIECE_HASH_LEN)
+            .map(|chunk| chunk.try_into().unwrap())
+            .collect();
+
+        let layout = match (dict.get("length"), dict.get("files")) {
+            (Some(_), Some(_)) => {
+                return Err(invalid("info", "both `length` and `files` are present"))
+            }
+            (Some(_), None) => FileLayout::Single {
+                length: non_negative(required_integer(dict, "info.", "length")?, "info.length")?,
+            },
+            (None, Some(files)) => FileLayout::Multi { files: parse_files(files)? },
+            (None, None) => return Err(MetainfoError::MissingKey("info.length".to_string())),
+        };
+
+        let info = InfoDict { name, piece_length, pieces, layout, private, version, files_v2 };
+        let expected_pieces = info.total_length().div_ceil(info.piece_length);
+        if expected_pieces != info.pieces.len() as u64 {
+            return Err(invalid(
+                "info.pieces",
+                &format!("expected {} hashes, found {}", expected_pieces, info.pieces.len()),
+            ));
+        }
+        if version == MetaVersion::Hybrid && !info.same_files_in_v1_and_v2() {
+            return Err(invalid("info", "v1 and v2 file lists describe different files"));
+        }
+        Ok(info)
+    }
+}
+
+fn parse_announce_list(value: &Bencode) -> Result<Vec<Vec<String>>, MetainfoError> {
+    let tiers = match value {
+        Bencode::List(tiers) => tiers,
+        _ => return Err(wrong_type("announce-list", "list")),
+    };
+    let mut result = Vec::with_capacity(tiers.len());
+    for (i, tier) in tiers.iter().enumerate() {
+        let urls = match tier {
+            Bencode::List(urls) => urls,
+            _ => return Err(wrong_type(&format!("announce-list[{i}]"), "list")),
+        };
+        let mut parsed = Vec::with_capacity(urls.len());
+        for (j, url) in urls.iter().enumerate() {
+            match as_string(url) {
+                Some(url) => parsed.push(url),
+                None => return Err(wrong_type(&format!("announce-list[{i}][{j}]"), "string")),
+            }
+        }
+        if !parsed.is_empty() {
+            result.push(parsed);
+        }
+    }
+    Ok(result)
+}
+
+fn parse_files(value: &Bencode) -> Result<Vec<FileEntry>, MetainfoError> {
+    let files = match value {
+        Bencode::List(files) => files,
+        _ => return Err(wrong_type("info.files", "list")),
+    };
+    if files.is_empty() {
+        return Err(invalid("info.files", "list is empty"));
+    }
+    let mut result = Vec::with_capacity(files.len());
+    for (i, file) in files.iter().enumerate() {
+        let prefix = format!("info.files[{i}].");
+        let dict = match file {
+            Bencode::Dictionary(_) => file,
+            _ => return Err(wrong_type(&format!("info.files[{i}]"), "dictionary")),
+        };
+        let length = non_negative(
+            required_integer(dict, &prefix, "length")?,
+            &format!("{prefix}length"),
+        )?;
+        let path = match dict.get("path") {
+            Some(Bencode::List(parts)) => {
+                let mut path = Vec::with_capacity(parts.len());
+                for (j, part) in parts.iter().enumerate() {
+                    match as_string(part) {
+                        Some(part) if is_path_element(&part) => path.push(part),
+                        Some(_) => return Err(invalid(&format!("{prefix}path[{j}]"), "invalid path element")),
+                        None => {
+                            return Err(wrong_type(&format!("{prefix}path[{j}]"), "string"))
+                        }
+                    }
+                }
+                path
+            }
+            Some(_) => return Err(wrong_type(&format!("{prefix}path"), "list")),
+            None => return Err(MetainfoError::MissingKey(format!("{prefix}path"))),
+        };
+        if path.is_empty() {
+            return Err(invalid(&format!("{prefix}path"), "path is empty"));
+        }
+        let padding = optional_string(dict, &prefix, "attr")?.is_some_and(|attr| attr.contains('p'));
+        result.push(FileEntry { length, path, padding });
+    }
+    Ok(result)
+}
+
+fn parse_file_tree(value: &Bencode) -> Result<Vec<FileV2>, MetainfoError> {
+    let mut files = Vec::new();
+    walk_file_tree(value, &mut Vec::new(), &mut files)?;
+    if files.is_empty() {
+        return Err(invalid("info.file tree", "tree has no files"));
+    }
+    Ok(files)
+}
+
+/// Directories are dictionaries keyed by path element, a file is a
+/// dictionary with the single key `""`.
+fn walk_file_tree(node: &Bencode, path: &mut Vec<String>, files: &mut Vec<FileV2>) -> Result<(), MetainfoError> {
+    let key = match path.is_empty() {
+        true => "info.file tree".to_string(),
+        false => format!("info.file tree.{}", path.join(".")),
+    };
+    let entries = node.as_dict().ok_or_else(|| wrong_type(&key, "dictionary"))?;
+    if let Some(file) = node.get("") {
+        if path.is_empty() || entries.len() != 1 {
+            return Err(invalid(&key, "file entry next to other entries"));
+        }
+        if file.as_dict().is_none() {
+            return Err(wrong_type(&key, "dictionary"));
+        }
+        let prefix = format!("{key}.");
+        let length = non_negative(required_integer(file, &prefix, "length")?, &format!("{prefix}length"))?;
+        let pieces_root = match file.get("pieces root") {
+            Some(root) => match root.as_bytes().map(<merkle::Hash>::try_from) {
+                Some(Ok(root)) => Some(root),
+                _ => return Err(invalid(&format!("{prefix}pieces root"), "must be 32 bytes")),
+            },
+            None if length > 0 => return Err(MetainfoError::MissingKey(format!("{prefix}pieces root"))),
+            None => None,
+        };
+        files.push(FileV2 { length, path: path.clone(), pieces_root });
+        return Ok(());
+    }
+    for (name, child) in entries {
+        match as_string(name) {
+            Some(name) if is_path_element(&name) => path.push(name),
+            _ => return Err(invalid(&key, "invalid path element")),
+        }
+        walk_file_tree(child, path, files)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+/// Layers are checked against the pieces roots, a torrent whose layers
+/// don't hash up to its roots could never complete.
+fn parse_piece_layers(
+    value: Option<&Bencode>,
+    info: &InfoDict,
+) -> Result<BTreeMap<merkle::Hash, Vec<merkle::Hash>>, MetainfoError> {
+    let mut layers = BTreeMap::new();
+    if let Some(value) = value {
+        let entries = value.as_dict().ok_or_else(|| wrong_type("piece layers", "dictionary"))?;
+        for (root, layer) in entries {
+            let root = match root.as_bytes().map(<merkle::Hash>::try_from) {
+                Some(Ok(root)) => root,
+                _ => return Err(invalid("piece layers", "key is not a 32 byte root")),
+            };
+            let layer = layer.as_bytes().ok_or_else(|| wrong_type("piece layers", "byte string"))?;
+            if layer.len() % merkle::HASH_LEN != 0 {
+                return Err(invalid("piece layers", "layer length is not a multiple of 32"));
+            }
+            let hashes: Vec<merkle::Hash> = layer.chunks_exact(merkle::HASH_LEN).map(|hash| hash.try_into().unwrap()).collect();
+            layers.insert(root, hashes);
+        }
+    }
+    for file in info.files_v2.iter().filter(|file| file.length > info.piece_length) {
+        let path = file.path.join("/");
+        let root = file.pieces_root.expect("non-empty files have a pieces root");
+        let layer = match layers.get(&root) {
+            Some(layer) => layer,
+            None => return Err(invalid("piece layers", &format!("no layer for `{path}`"))),
+        };
+        if layer.len() as u64 != file.length.div_ceil(info.piece_length)
+            || merkle::root_from_layer(layer, info.piece_length) != root
+        {
+            return Err(invalid("piece layers", &format!("layer of `{path}` does not match its pieces root")));
+        }
+    }
+    Ok(layers)
+}
+
+fn as_string(value: &Bencode) -> Option<String> {
+    match value {
+        Bencode::String(value) => Some(value.clone()),
+        Bencode::Bytes(value) => Some(String::from_utf8_lossy(value).into_owned()),
+        _ => None,
+    }
+}
+
+fn required_string(dict: &Bencode, prefix: &str, key: &str) -> Result<String, MetainfoError> {
+    optional_string(dict, prefix, key)?.ok_or_else(|| MetainfoError::MissingKey(format!("{prefix}{key}")))
+}
+
+fn optional_string(dict: &Bencode, prefix: &str, key: &str) -> Result<Option<String>, MetainfoError> {
+    match dict.get(key) {
+        Some(value) => match as_string(value) {
+            Some(value) => Ok(Some(value)),
+            None => Err(wrong_type(&format!("{prefix}{key}"), "string")),
+        },
+        None => Ok(None),
+    }
+}
+
+fn required_integer(dict: &Bencode, prefix: &str, key: &str) -> Result<i64, MetainfoError> {
+    optional_integer(dict, prefix, key)?.ok_or_else(|| MetainfoError::MissingKey(format!("{prefix}{key}")))
+}
+
+fn optional_integer(dict: &Bencode, prefix: &str, key: &str) -> Result<Option<i64>, MetainfoError> {
+    match dict.get(key) {
+        Some(Bencode::Integer(value)) => Ok(Some(*value)),
+        Some(_) => Err(wrong_type(&format!("{prefix}{key}"), "integer")),
+        None => Ok(None),
+    }
+}
+
+fn non_negative(value: i64, key: &str) -> Result<u64, MetainfoError> {
+    u64::try_from(value).map_err(|_| invalid(key, "must not be negative"))
+}
+
+/// A file or directory name that can't lead out of the torrent's
+/// directory.
+fn is_path_element(part: &str) -> bool {
+    !part.is_empty() && part != "." && part != ".." && !part.contains('/')
+}
+
+fn wrong_type(key: &str, expected: &'static str) -> MetainfoError {
+    MetainfoError::WrongType { key: key.to_string(), expected }
+}
+
+fn invalid(key: &str, reason: &str) -> MetainfoError {
+    MetainfoError::InvalidValue { key: key.to_string(), reason: reason.to_string() }
+}
+
+#[tokio::test]
+async fn test_metainfo_single_file() {
+    let metainfo = crate::read_metainfo_from_file("test2.torrent").await.unwrap();
+    assert_eq!(metainfo.info_hash.to_hex(), "36582683157013417abb7c7c0ac2ab4fac575f42");
+    assert_eq!(metainfo.announce.as_deref(), Some("udp://bt.rutor.org:2710"));
+    assert_eq!(metainfo.announce_list.len(), 5);
+    assert_eq!(metainfo.info.name, "127.Chasov.2010.x264.BDRip.AVC.MediaClub.mkv");
+    assert_eq!(metainfo.info.layout, FileLayout::Single { length: 4480714810 });
+    assert_eq!(metainfo.info.piece_length, 4194304);
+    assert_eq!(metainfo.info.piece_count(), 1069);
+    assert!(!metainfo.info.private);
+
+    let data = tokio::fs::read("test2.torrent").await.unwrap();
+    let strict = Metainfo::from_bytes_with_options(&data, &DecodeOptions::strict()).unwrap();
+    assert_eq!(strict, metainfo);
+}
+
+#[tokio::test]
+async fn test_metainfo_multi_file() {
+    let metainfo = crate::read_metainfo_from_file("../test.torrent").await.unwrap();
+    assert_eq!(metainfo.info_hash.to_hex(), "ebf84c291e1935558e41f8477de01fa4cf25456a");
+    assert_eq!(metainfo.creation_date, Some(1629365496));
+    match &metainfo.info.layout {
+        FileLayout::Multi { files } => assert!(!files.is_empty()),
+        FileLayout::Single { .. } => panic!("expected multi-file torrent"),
+    }
+}
+
+#[test]
+fn test_metainfo_errors() {
+    let info = |entries: Vec<(&str, Bencode)>| {
+        Bencode::Dictionary(vec![(
+            Bencode::String("info".to_string()),
+            Bencode::Dictionary(
+                entries.into_iter().map(|(k, v)| (Bencode::String(k.to_string()), v)).collect(),
+            ),
+        )])
+    };
+    let hash: &[u8] = b"";
+    assert_eq!(Metainfo::from_bytes(b"i1e"), Err(MetainfoError::NotADictionary));
+    assert_eq!(
+        Metainfo::from_bytes(b"d8:announce3:urle"),
+        Err(MetainfoError::MissingKey("info".to_string()))
+    );
+    assert_eq!(
+        Metainfo::from_bencode(&info(vec![("piece length", Bencode::Integer(16384))]), hash),
+        Err(MetainfoError::MissingKey("info.name".to_string()))
+    );
+    assert_eq!(
+        Metainfo::from_bencode(&info(vec![
+            ("name", Bencode::String("a".to_string())),
+            ("piece length", Bencode::String("16384".to_string())),
+        ]), hash),
+        Err(wrong_type("info.piece length", "integer"))
+    );
+    assert_eq!(
+        Metainfo::from_bencode(&info(vec![
+            ("name", Bencode::String("a".to_string())),
+            ("piece length", Bencode::Integer(16384)),
+            ("pieces", Bencode::Bytes(vec![0; 19])),
+            ("length", Bencode::Integer(10)),
+        ]), hash),
+        Err(invalid("info.pieces", "length is not a multiple of 20"))
+    );
+}
+
+#[test]
+fn test_metainfo_rejects_escaping_paths() {
+    let dict = |entries: Vec<(&str, Bencode)>| {
+        Bencode::Dictionary(entries.into_iter().map(|(k, v)| (Bencode::String(k.to_string()), v)).collect())
+    };
+    assert_eq!(
+        InfoDict::from_bencode(&dict(vec![("name", Bencode::String("..".to_string()))])),
+        Err(invalid("info.name", "invalid path element"))
+    );
+    for bad in ["..", ".", "", "a/b"] {
+        let path = Bencode::List(vec![Bencode::String("dir".to_string()), Bencode::String(bad.to_string())]);
+        let files = Bencode::List(vec![dict(vec![("length", Bencode::Integer(10)), ("path", path)])]);
+        assert_eq!(
+            InfoDict::from_bencode(&dict(vec![
+                ("name", Bencode::String("a".to_string())),
+                ("piece length", Bencode::Integer(16384)),
+                ("pieces", Bencode::Bytes(vec![0; 20])),
+                ("files", files),
+            ])),
+            Err(invalid("info.files[0].path[1]", "invalid path element"))
+        );
+    }
+}
@@ -0,0 +1,46 @@
+/// Controls how forgiving `decode_bencode_with_options` is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Enforce canonical BEP 3 encoding: no leading zeros, no `i-0e`,
+    /// sorted unique dictionary keys and nothing after the root value.
+    pub strict: bool,
+    /// Maximum nesting of lists and dictionaries.
+    pub max_depth: usize,
+    /// Maximum encoded size of the root value in bytes.
+    pub max_length: usize,
+}
+
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+pub const DEFAULT_MAX_LENGTH: usize = 128 * 1024 * 1024;
+
+impl DecodeOptions {
+    /// Canonical encoding only, for validating torrents we publish.
+    pub fn strict() -> Self {
+        DecodeOptions { strict: true, ..Default::default() }
+    }
+
+    /// Accepts the non-canonical encodings found in real-world files.
+    pub fn lenient() -> Self {
+        DecodeOptions::default()
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            strict: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_length: DEFAULT_MAX_LENGTH,
+        }
+    }
+}
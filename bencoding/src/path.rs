@@ -0,0 +1,336 @@
+//! Accessors and path queries on [`Bencode`].
+//!
+//! A path is a list of dictionary keys separated by `.`, each key may be
+//! followed by list indices: `info.files[3].path[0]`. A path may also start
+//! with an index when the root is a list. `\` escapes a `.`, `[` or `\`
+//! that is part of a key.
+
+use crate::error::PathError;
+use crate::Bencode;
+
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Segments of `path`, each with the length of the path prefix that ends
+/// with it, so errors can name the part that failed.
+fn parse_path(path: &str) -> Result<Vec<(Segment, usize)>, PathError> {
+    let syntax = |reason| PathError::Syntax { path: path.to_string(), reason };
+    let bytes = path.as_bytes();
+    let mut segments = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let mut key = String::new();
+        while i < bytes.len() && bytes[i] != b'.' && bytes[i] != b'[' {
+            if bytes[i] == b'\\' {
+                i += 1;
+                if i == bytes.len() {
+                    return Err(syntax("dangling `\\`"));
+                }
+            }
+            let c = path[i..].chars().next().unwrap();
+            key.push(c);
+            i += c.len_utf8();
+        }
+        if !key.is_empty() {
+            segments.push((Segment::Key(key), i));
+        } else if !segments.is_empty() || i == bytes.len() || bytes[i] != b'[' {
+            return Err(syntax("empty key"));
+        }
+        while i < bytes.len() && bytes[i] == b'[' {
+            let close = match path[i..].find(']') {
+                Some(close) => i + close,
+                None => return Err(syntax("unclosed `[`")),
+            };
+            let index = path[i + 1..close].parse().map_err(|_| syntax("index is not a number"))?;
+            i = close + 1;
+            segments.push((Segment::Index(index), i));
+        }
+        if i < bytes.len() {
+            if bytes[i] != b'.' {
+                return Err(syntax("expected `.` or `[` after `]`"));
+            }
+            i += 1;
+            if i == bytes.len() {
+                return Err(syntax("empty key"));
+            }
+        }
+    }
+    Ok(segments)
+}
+
+/// UTF-8 keys become `String`, like the decoder does, so lookups by `&str`
+/// and by raw bytes find the same entries.
+fn key_from_bytes(key: &[u8]) -> Bencode {
+    match std::str::from_utf8(key) {
+        Ok(key) => Bencode::String(key.to_string()),
+        Err(_) => Bencode::Bytes(key.to_vec()),
+    }
+}
+
+impl Bencode {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Bencode::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Raw bytes of either string variant.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Bencode::String(value) => Some(value.as_bytes()),
+            Bencode::Bytes(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Bencode::String(value) => Some(value),
+            Bencode::Bytes(value) => std::str::from_utf8(value).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Bencode]> {
+        match self {
+            Bencode::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_list_mut(&mut self) -> Option<&mut Vec<Bencode>> {
+        match self {
+            Bencode::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_dict(&self) -> Option<&[(Bencode, Bencode)]> {
+        match self {
+            Bencode::Dictionary(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    pub fn as_dict_mut(&mut self) -> Option<&mut Vec<(Bencode, Bencode)>> {
+        match self {
+            Bencode::Dictionary(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    /// Value of `key` when `self` is a dictionary. With duplicate keys,
+    /// which only the lenient decoder lets through, the first one wins.
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<&Bencode> {
+        let key = key.as_ref();
+        self.as_dict()?
+            .iter()
+            .find(|(k, _)| k.as_bytes() == Some(key))
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_mut<K: AsRef<[u8]>>(&mut self, key: K) -> Option<&mut Bencode> {
+        let key = key.as_ref();
+        self.as_dict_mut()?
+            .iter_mut()
+            .find(|(k, _)| k.as_bytes() == Some(key))
+            .map(|(_, value)| value)
+    }
+
+    /// Value at `path`, `None` when any part is missing or the path is
+    /// malformed.
+    pub fn query(&self, path: &str) -> Option<&Bencode> {
+        let mut current = self;
+        for (segment, _) in parse_path(path).ok()? {
+            current = match segment {
+                Segment::Key(key) => current.get(key)?,
+                Segment::Index(index) => current.as_list()?.get(index)?,
+            };
+        }
+        Some(current)
+    }
+
+    pub fn query_mut(&mut self, path: &str) -> Option<&mut Bencode> {
+        let segments = parse_path(path).ok()?;
+        self.walk_mut(path, &segments).ok()
+    }
+
+    fn walk_mut(&mut self, path: &str, segments: &[(Segment, usize)]) -> Result<&mut Bencode, PathError> {
+        let mut current = self;
+        let mut parent_end = 0;
+        for (segment, end) in segments {
+            let wrong_type = |expected| PathError::WrongType { path: path[..parent_end].to_string(), expected };
+            current = match segment {
+                Segment::Key(key) => match current {
+                    Bencode::Dictionary(_) => current.get_mut(key),
+                    _ => return Err(wrong_type("dictionary")),
+                },
+                Segment::Index(index) => match current {
+                    Bencode::List(list) => list.get_mut(*index),
+                    _ => return Err(wrong_type("list")),
+                },
+            }
+            .ok_or_else(|| PathError::NotFound(path[..*end].to_string()))?;
+            parent_end = *end;
+        }
+        Ok(current)
+    }
+
+    /// Sets `key` of a dictionary and returns the previous value. New keys
+    /// go to their sorted position, so a canonical dictionary stays
+    /// canonical.
+    pub fn insert<K: AsRef<[u8]>>(&mut self, key: K, value: Bencode) -> Result<Option<Bencode>, PathError> {
+        let key = key.as_ref();
+        let dict = self
+            .as_dict_mut()
+            .ok_or(PathError::WrongType { path: String::new(), expected: "dictionary" })?;
+        if let Some((_, old)) = dict.iter_mut().find(|(k, _)| k.as_bytes() == Some(key)) {
+            return Ok(Some(std::mem::replace(old, value)));
+        }
+        let position = dict.partition_point(|(k, _)| k.as_bytes().is_some_and(|k| k < key));
+        dict.insert(position, (key_from_bytes(key), value));
+        Ok(None)
+    }
+
+    /// Removes `key` from a dictionary, keeping the order of the rest.
+    pub fn remove<K: AsRef<[u8]>>(&mut self, key: K) -> Option<Bencode> {
+        let key = key.as_ref();
+        let dict = self.as_dict_mut()?;
+        let position = dict.iter().position(|(k, _)| k.as_bytes() == Some(key))?;
+        Some(dict.remove(position).1)
+    }
+
+    /// Inserts or replaces the value at `path` and returns the previous
+    /// one. Everything but the last segment has to exist already, an index
+    /// one past the end of a list appends.
+    pub fn set_path(&mut self, path: &str, value: Bencode) -> Result<Option<Bencode>, PathError> {
+        let segments = parse_path(path)?;
+        let (last, parents) = match segments.split_last() {
+            Some(((last, _), parents)) => (last, parents),
+            None => return Ok(Some(std::mem::replace(self, value))),
+        };
+        let parent_path = &path[..parents.last().map_or(0, |(_, end)| *end)];
+        let parent = self.walk_mut(path, parents)?;
+        match (last, parent) {
+            (Segment::Key(key), parent @ Bencode::Dictionary(_)) => parent.insert(key, value),
+            (Segment::Index(index), Bencode::List(list)) => {
+                if *index < list.len() {
+                    Ok(Some(std::mem::replace(&mut list[*index], value)))
+                } else if *index == list.len() {
+                    list.push(value);
+                    Ok(None)
+                } else {
+                    Err(PathError::NotFound(path.to_string()))
+                }
+            }
+            (Segment::Key(_), _) => {
+                Err(PathError::WrongType { path: parent_path.to_string(), expected: "dictionary" })
+            }
+            (Segment::Index(_), _) => {
+                Err(PathError::WrongType { path: parent_path.to_string(), expected: "list" })
+            }
+        }
+    }
+
+    /// Removes the dictionary entry or list element at `path`. `Ok(None)`
+    /// when the parent exists but the last segment does not.
+    pub fn remove_path(&mut self, path: &str) -> Result<Option<Bencode>, PathError> {
+        let segments = parse_path(path)?;
+        let (last, parents) = match segments.split_last() {
+            Some(((last, _), parents)) => (last, parents),
+            None => return Err(PathError::Syntax { path: path.to_string(), reason: "the root can't be removed" }),
+        };
+        let parent_path = &path[..parents.last().map_or(0, |(_, end)| *end)];
+        let parent = self.walk_mut(path, parents)?;
+        match (last, parent) {
+            (Segment::Key(key), parent @ Bencode::Dictionary(_)) => Ok(parent.remove(key)),
+            (Segment::Index(index), Bencode::List(list)) => {
+                Ok((*index < list.len()).then(|| list.remove(*index)))
+            }
+            (Segment::Key(_), _) => {
+                Err(PathError::WrongType { path: parent_path.to_string(), expected: "dictionary" })
+            }
+            (Segment::Index(_), _) => {
+                Err(PathError::WrongType { path: parent_path.to_string(), expected: "list" })
+            }
+        }
+    }
+}
+
+impl From<i64> for Bencode {
+    fn from(value: i64) -> Self {
+        Bencode::Integer(value)
+    }
+}
+
+impl From<&str> for Bencode {
+    fn from(value: &str) -> Self {
+        Bencode::String(value.to_string())
+    }
+}
+
+impl From<String> for Bencode {
+    fn from(value: String) -> Self {
+        Bencode::String(value)
+    }
+}
+
+/// Valid UTF-8 becomes `String`, the same split the decoder makes.
+impl From<Vec<u8>> for Bencode {
+    fn from(value: Vec<u8>) -> Self {
+        match String::from_utf8(value) {
+            Ok(value) => Bencode::String(value),
+            Err(err) => Bencode::Bytes(err.into_bytes()),
+        }
+    }
+}
+
+impl From<Vec<Bencode>> for Bencode {
+    fn from(value: Vec<Bencode>) -> Self {
+        Bencode::List(value)
+    }
+}
+
+#[test]
+fn test_query_and_mutate() {
+    let mut torrent = crate::decode_bencode(
+        b"d8:announce3:url4:infod5:filesld6:lengthi1e4:pathl1:aeed6:lengthi2e4:pathl1:b1:ceee4:name3:dire1:z1:\xffe",
+    )
+    .unwrap();
+    assert_eq!(torrent.query("info.files[1].path[1]").and_then(Bencode::as_str), Some("c"));
+    assert_eq!(torrent.query("info.files[0].length").and_then(Bencode::as_int), Some(1));
+    assert_eq!(torrent.get("z").and_then(Bencode::as_bytes), Some(&b"\xff"[..]));
+    assert_eq!(torrent.query("info.files[2]"), None);
+    assert_eq!(torrent.query("info..name"), None);
+    assert_eq!(torrent.query(""), Some(&torrent));
+
+    assert_eq!(torrent.insert("comment", "hi".into()), Ok(None));
+    assert_eq!(torrent.set_path("info.private", 1.into()), Ok(None));
+    assert_eq!(torrent.set_path("announce", "udp://x".into()), Ok(Some("url".into())));
+    assert_eq!(torrent.set_path("info.files[1].path[2]", "d".into()), Ok(None));
+    assert_eq!(torrent.remove_path("info.files[0]").unwrap().and_then(|file| file.get("length").cloned()), Some(1.into()));
+    assert_eq!(torrent.remove("missing"), None);
+    assert_eq!(
+        torrent.to_bencode_bytes().unwrap(),
+        b"d8:announce7:udp://x7:comment2:hi4:infod5:filesld6:lengthi2e4:pathl1:b1:c1:deee4:name3:dir7:privatei1ee1:z1:\xffe"
+    );
+    // Inserting keeps the in-memory order canonical, not just the encoding.
+    let keys: Vec<_> = torrent.as_dict().unwrap().iter().map(|(k, _)| k.as_str().unwrap()).collect();
+    assert_eq!(keys, ["announce", "comment", "info", "z"]);
+
+    assert_eq!(
+        torrent.set_path("info.files[5].length", 1.into()),
+        Err(PathError::NotFound("info.files[5]".to_string()))
+    );
+    assert_eq!(
+        torrent.set_path("info.name.x", 1.into()),
+        Err(PathError::WrongType { path: "info.name".to_string(), expected: "dictionary" })
+    );
+    assert!(matches!(torrent.set_path("info[", 1.into()), Err(PathError::Syntax { .. })));
+    let mut dotted = Bencode::Dictionary(vec![]);
+    dotted.set_path(r"a\.b", 1.into()).unwrap();
+    assert_eq!(dotted.get("a.b"), Some(&Bencode::Integer(1)));
+}
@@ -0,0 +1,432 @@
+//! Incremental decoding for input that arrives in chunks: tracker bodies,
+//! extension messages, DHT packets.
+
+use std::io;
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{Bencode, DecodeError, DecodeErrorKind, DecodeOptions};
+
+/// Longest digit run of an `i64` or a string length, with a sign.
+const MAX_DIGITS: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    ListStart,
+    DictionaryStart,
+    /// Closes the innermost list or dictionary.
+    End,
+    Integer(i64),
+    String(Vec<u8>),
+}
+
+enum State {
+    /// Expecting the first byte of a value, or `e` inside a container.
+    Value,
+    Integer { digits: Vec<u8>, start: usize },
+    Length { digits: Vec<u8>, start: usize },
+    String { remaining: usize, buffer: Vec<u8>, start: usize },
+    /// Root value is complete.
+    Done,
+}
+
+struct Container {
+    dictionary: bool,
+    expect_key: bool,
+    last_key: Option<Vec<u8>>,
+}
+
+/// Push parser: feed it bytes, it emits events and stops right after the
+/// root value, so whatever follows is left to the caller.
+pub struct PushParser {
+    options: DecodeOptions,
+    state: State,
+    stack: Vec<Container>,
+    /// Bytes of the current root value consumed so far.
+    offset: usize,
+}
+
+impl PushParser {
+    pub fn new(options: DecodeOptions) -> Self {
+        PushParser { options, state: State::Value, stack: Vec::new(), offset: 0 }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// True while a value has been started but not finished.
+    pub fn in_progress(&self) -> bool {
+        self.offset > 0 && !self.is_complete()
+    }
+
+    pub fn consumed(&self) -> usize {
+        self.offset
+    }
+
+    /// Starts over for the next root value.
+    pub fn reset(&mut self) {
+        self.state = State::Value;
+        self.stack.clear();
+        self.offset = 0;
+    }
+
+    /// Consumes bytes until the root value is complete or `input` runs out
+    /// and returns how many bytes were used.
+    pub fn parse(&mut self, input: &[u8], events: &mut Vec<Event>) -> Result<usize, DecodeError> {
+        let mut i = 0;
+        while i < input.len() && !self.is_complete() {
+            if self.offset >= self.options.max_length {
+                return Err(self.error(DecodeErrorKind::LengthLimitExceeded));
+            }
+            let byte = input[i];
+            let mut used = 1;
+            match std::mem::replace(&mut self.state, State::Value) {
+                State::Value => self.start_value(byte, events)?,
+                State::Integer { mut digits, start } => match byte {
+                    b'e' => {
+                        let value = parse_integer(&digits, self.options.strict)
+                            .map_err(|kind| DecodeError::new(start, kind))?;
+                        events.push(Event::Integer(value));
+                        self.finish_value();
+                    },
+                    b'0'..=b'9' | b'-' if digits.len() < MAX_DIGITS => {
+                        digits.push(byte);
+                        self.state = State::Integer { digits, start };
+                    },
+                    _ => return Err(self.error(DecodeErrorKind::InvalidInteger)),
+                },
+                State::Length { mut digits, start } => match byte {
+                    b':' => {
+                        let len = parse_length(&digits, self.options.strict)
+                            .map_err(|kind| DecodeError::new(start, kind))?;
+                        if len > self.options.max_length {
+                            return Err(DecodeError::new(start, DecodeErrorKind::LengthLimitExceeded));
+                        }
+                        let buffer = Vec::with_capacity(len.min(64 * 1024));
+                        self.state = State::String { remaining: len, buffer, start };
+                        if len == 0 {
+                            self.finish_string(events)?;
+                        }
+                    },
+                    b'0'..=b'9' if digits.len() < MAX_DIGITS => {
+                        digits.push(byte);
+                        self.state = State::Length { digits, start };
+                    },
+                    _ => return Err(self.error(DecodeErrorKind::InvalidStringLength)),
+                },
+                State::String { remaining, mut buffer, start } => {
+                    used = remaining.min(input.len() - i);
+                    buffer.extend_from_slice(&input[i..i + used]);
+                    self.state = State::String { remaining: remaining - used, buffer, start };
+                    if remaining == used {
+                        self.finish_string(events)?;
+                    }
+                },
+                State::Done => unreachable!(),
+            }
+            i += used;
+            self.offset += used;
+        }
+        Ok(i)
+    }
+
+    fn error(&self, kind: DecodeErrorKind) -> DecodeError {
+        DecodeError::new(self.offset, kind)
+    }
+
+    fn start_value(&mut self, byte: u8, events: &mut Vec<Event>) -> Result<(), DecodeError> {
+        let expect_key = self.stack.last().is_some_and(|top| top.dictionary && top.expect_key);
+        match byte {
+            b'e' => match self.stack.last() {
+                Some(top) if !top.dictionary || top.expect_key => {
+                    self.stack.pop();
+                    events.push(Event::End);
+                    self.finish_value();
+                },
+                _ => return Err(self.error(DecodeErrorKind::UnexpectedByte(byte))),
+            },
+            b'0'..=b'9' => {
+                self.state = State::Length { digits: vec![byte], start: self.offset };
+            },
+            _ if expect_key => return Err(self.error(DecodeErrorKind::NonStringKey)),
+            b'i' => {
+                self.state = State::Integer { digits: Vec::new(), start: self.offset + 1 };
+            },
+            b'l' | b'd' => {
+                if self.stack.len() >= self.options.max_depth {
+                    return Err(self.error(DecodeErrorKind::DepthLimitExceeded));
+                }
+                let dictionary = byte == b'd';
+                self.stack.push(Container { dictionary, expect_key: dictionary, last_key: None });
+                events.push(if dictionary { Event::DictionaryStart } else { Event::ListStart });
+            },
+            _ => return Err(self.error(DecodeErrorKind::UnexpectedByte(byte))),
+        }
+        Ok(())
+    }
+
+    fn finish_string(&mut self, events: &mut Vec<Event>) -> Result<(), DecodeError> {
+        let (buffer, start) = match std::mem::replace(&mut self.state, State::Value) {
+            State::String { buffer, start, .. } => (buffer, start),
+            _ => unreachable!(),
+        };
+        let strict = self.options.strict;
+        if let Some(top) = self.stack.last_mut().filter(|top| top.dictionary && top.expect_key) {
+            if let (true, Some(last_key)) = (strict, &top.last_key) {
+                match last_key.as_slice().cmp(&buffer) {
+                    std::cmp::Ordering::Less => {},
+                    std::cmp::Ordering::Equal => return Err(DecodeError::new(start, DecodeErrorKind::DuplicateKey)),
+                    std::cmp::Ordering::Greater => return Err(DecodeError::new(start, DecodeErrorKind::UnsortedKeys)),
+                }
+            }
+            if strict {
+                top.last_key = Some(buffer.clone());
+            }
+        }
+        events.push(Event::String(buffer));
+        self.finish_value();
+        Ok(())
+    }
+
+    fn finish_value(&mut self) {
+        match self.stack.last_mut() {
+            Some(top) => {
+                if top.dictionary {
+                    top.expect_key = !top.expect_key;
+                }
+                self.state = State::Value;
+            },
+            None => self.state = State::Done,
+        }
+    }
+}
+
+fn parse_integer(digits: &[u8], strict: bool) -> Result<i64, DecodeErrorKind> {
+    let unsigned = digits.strip_prefix(b"-").unwrap_or(digits);
+    if unsigned.is_empty() || unsigned.contains(&b'-') {
+        return Err(DecodeErrorKind::InvalidInteger);
+    }
+    if strict && unsigned == b"0" && digits.len() == 2 {
+        return Err(DecodeErrorKind::NegativeZero);
+    }
+    if strict && unsigned.len() > 1 && unsigned[0] == b'0' {
+        return Err(DecodeErrorKind::LeadingZeros);
+    }
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse::<i64>().ok())
+        .ok_or(DecodeErrorKind::InvalidInteger)
+}
+
+fn parse_length(digits: &[u8], strict: bool) -> Result<usize, DecodeErrorKind> {
+    if strict && digits.len() > 1 && digits[0] == b'0' {
+        return Err(DecodeErrorKind::LeadingZeros);
+    }
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse::<usize>().ok())
+        .ok_or(DecodeErrorKind::InvalidStringLength)
+}
+
+/// Result of [`StreamDecoder::feed`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct Feed {
+    /// Bytes of the input that were used, the rest belongs to later values.
+    pub consumed: usize,
+    /// Set when a root value was completed by this call.
+    pub value: Option<Bencode>,
+}
+
+enum Partial {
+    List(Vec<Bencode>),
+    Dictionary(Vec<(Bencode, Bencode)>, Option<Bencode>),
+}
+
+/// Builds whole `Bencode` values out of the events of a [`PushParser`].
+pub struct StreamDecoder {
+    parser: PushParser,
+    events: Vec<Event>,
+    partial: Vec<Partial>,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        StreamDecoder::with_options(DecodeOptions::default())
+    }
+
+    pub fn with_options(options: DecodeOptions) -> Self {
+        StreamDecoder { parser: PushParser::new(options), events: Vec::new(), partial: Vec::new() }
+    }
+
+    /// True while a value has been started but not finished.
+    pub fn in_progress(&self) -> bool {
+        self.parser.in_progress()
+    }
+
+    pub fn feed(&mut self, input: &[u8]) -> Result<Feed, DecodeError> {
+        let consumed = match self.parser.parse(input, &mut self.events) {
+            Ok(consumed) => consumed,
+            Err(err) => {
+                self.parser.reset();
+                self.events.clear();
+                self.partial.clear();
+                return Err(err);
+            },
+        };
+        let mut value = None;
+        for event in self.events.drain(..) {
+            let finished = match event {
+                Event::ListStart => {
+                    self.partial.push(Partial::List(Vec::new()));
+                    continue;
+                },
+                Event::DictionaryStart => {
+                    self.partial.push(Partial::Dictionary(Vec::new(), None));
+                    continue;
+                },
+                Event::End => match self.partial.pop() {
+                    Some(Partial::List(list)) => Bencode::List(list),
+                    Some(Partial::Dictionary(dict, _)) => Bencode::Dictionary(dict),
+                    None => unreachable!(),
+                },
+                Event::Integer(integer) => Bencode::Integer(integer),
+                Event::String(string) => match String::from_utf8(string) {
+                    Ok(string) => Bencode::String(string),
+                    Err(err) => Bencode::Bytes(err.into_bytes()),
+                },
+            };
+            match self.partial.last_mut() {
+                Some(Partial::List(list)) => list.push(finished),
+                Some(Partial::Dictionary(dict, key)) => match key.take() {
+                    Some(key) => dict.push((key, finished)),
+                    None => *key = Some(finished),
+                },
+                None => value = Some(finished),
+            }
+        }
+        if self.parser.is_complete() {
+            self.parser.reset();
+        }
+        Ok(Feed { consumed, value })
+    }
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        StreamDecoder::new()
+    }
+}
+
+/// `tokio_util` codec, wrap an `AsyncRead` in `FramedRead` to get a stream
+/// of values.
+#[derive(Default)]
+pub struct BencodeCodec {
+    decoder: StreamDecoder,
+}
+
+impl BencodeCodec {
+    pub fn new() -> Self {
+        BencodeCodec::default()
+    }
+
+    pub fn with_options(options: DecodeOptions) -> Self {
+        BencodeCodec { decoder: StreamDecoder::with_options(options) }
+    }
+}
+
+impl Decoder for BencodeCodec {
+    type Item = Bencode;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Bencode>, io::Error> {
+        let feed = self
+            .decoder
+            .feed(src)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        src.advance(feed.consumed);
+        Ok(feed.value)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Bencode>, io::Error> {
+        match self.decode(src)? {
+            Some(value) => Ok(Some(value)),
+            None if self.decoder.in_progress() => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stream ended in the middle of a value",
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder<&Bencode> for BencodeCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: &Bencode, dst: &mut BytesMut) -> Result<(), io::Error> {
+        item.write_to(&mut dst.writer())
+    }
+}
+
+#[test]
+fn test_stream_decoder_chunks() {
+    let data = b"d4:spaml1:ai-3ee3:cow3:mooe4:spam";
+    let mut decoder = StreamDecoder::new();
+    let mut values = Vec::new();
+    let mut consumed = 0;
+    // One byte at a time is the worst case for the state machine.
+    for chunk in data.chunks(1) {
+        let mut chunk = chunk;
+        while !chunk.is_empty() {
+            let feed = decoder.feed(chunk).unwrap();
+            consumed += feed.consumed;
+            chunk = &chunk[feed.consumed..];
+            values.extend(feed.value);
+        }
+    }
+    assert_eq!(consumed, data.len());
+    assert_eq!(
+        values,
+        vec![
+            crate::decode_bencode(b"d4:spaml1:ai-3ee3:cow3:mooe").unwrap(),
+            Bencode::String("spam".to_string()),
+        ]
+    );
+
+    let feed = decoder.feed(b"i1ei2e").unwrap();
+    assert_eq!(feed, Feed { consumed: 3, value: Some(Bencode::Integer(1)) });
+
+    let mut strict = StreamDecoder::with_options(DecodeOptions::strict());
+    assert_eq!(strict.feed(b"d1:b"), Ok(Feed { consumed: 4, value: None }));
+    assert_eq!(
+        strict.feed(b"i1e1:a"),
+        Err(DecodeError::new(7, DecodeErrorKind::UnsortedKeys))
+    );
+    let mut limited = StreamDecoder::with_options(DecodeOptions::lenient().max_length(8));
+    assert_eq!(
+        limited.feed(b"100:"),
+        Err(DecodeError::new(0, DecodeErrorKind::LengthLimitExceeded))
+    );
+}
+
+#[tokio::test]
+async fn test_codec_over_async_read() {
+    use futures::StreamExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio_util::codec::FramedRead;
+
+    let (mut writer, reader) = tokio::io::duplex(4);
+    tokio::spawn(async move {
+        writer.write_all(b"d8:intervali1800e5:peers6:\x7f\x00\x00\x01\x1a\xe1e").await.unwrap();
+        writer.write_all(b"li1ee").await.unwrap();
+        writer.write_all(b"l1:").await.unwrap();
+    });
+    let mut frames = FramedRead::new(reader, BencodeCodec::new());
+    let first = frames.next().await.unwrap().unwrap();
+    assert_eq!(first.to_bencode_bytes().unwrap(), b"d8:intervali1800e5:peers6:\x7f\x00\x00\x01\x1a\xe1e");
+    let second = frames.next().await.unwrap().unwrap();
+    assert_eq!(second, Bencode::List(vec![Bencode::Integer(1)]));
+    let error = frames.next().await.unwrap().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+}
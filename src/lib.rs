@@ -0,0 +1,15 @@
+//! Building blocks of the client: magnet links, metadata exchange, the
+//! tracker clients, the peer wire protocol, piece picking, storage and resume
+//! data. `main.rs` is the command line front end on top of them.
+
+pub mod magnet;
+pub mod metadata;
+pub mod network_manager;
+pub mod peer_messaging;
+pub mod tracker_manager;
+pub mod peer_wire;
+pub mod peer_id;
+pub mod piece_picker;
+pub mod request_queue;
+pub mod storage;
+pub mod resume;
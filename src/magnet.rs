@@ -0,0 +1,257 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use bencoding::info_hash::url_encode_bytes;
+use bencoding::{InfoHash, InfoHashV2, Metainfo};
+
+/// Multihash prefix of a SHA-256 digest: function code 0x12, length 32.
+const SHA256_MULTIHASH: [u8; 2] = [0x12, 0x20];
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Magnet link (BEP 9, BEP 53), a torrent without its metadata.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Magnet {
+    /// `xt=urn:btih:`, hex or base32.
+    pub info_hash: Option<InfoHash>,
+    /// `xt=urn:btmh:`, a SHA-256 multihash (BEP 52).
+    pub info_hash_v2: Option<InfoHashV2>,
+    /// `dn`, name to show until the metadata arrives.
+    pub display_name: Option<String>,
+    /// `tr`, in link order.
+    pub trackers: Vec<String>,
+    /// `ws`, HTTP seeds.
+    pub web_seeds: Vec<String>,
+    /// `x.pe`, `host:port` of peers to contact directly.
+    pub peers: Vec<String>,
+    /// `so`, indices of the files to download (BEP 53), empty for all.
+    pub select_only: Vec<RangeInclusive<usize>>,
+}
+
+impl Magnet {
+    pub fn parse<T: AsRef<str>>(uri: T) -> Result<Self, String> {
+        let uri = uri.as_ref();
+        let query = match uri.get(..8) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("magnet:?") => &uri[8..],
+            _ => return Err(format!("`{uri}` is not a magnet link")),
+        };
+        let mut magnet = Magnet::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            // Repeated parameters may be numbered, `tr.1=...&tr.2=...`.
+            let key = key.split_once('.').filter(|(_, n)| n.parse::<u32>().is_ok()).map_or(key, |(key, _)| key);
+            // A literal `+` is common in tracker urls, only the name is form encoded.
+            let value = percent_decode(value, key == "dn")?;
+            match key {
+                "xt" => magnet.parse_exact_topic(&value)?,
+                "dn" => magnet.display_name = Some(value),
+                "tr" => magnet.trackers.push(value),
+                "ws" => magnet.web_seeds.push(value),
+                "x.pe" => magnet.peers.push(value),
+                "so" => magnet.select_only.extend(parse_select_only(&value)?),
+                _ => {}
+            }
+        }
+        if magnet.info_hash.is_none() && magnet.info_hash_v2.is_none() {
+            return Err("magnet link has no btih or btmh info-hash".to_string());
+        }
+        Ok(magnet)
+    }
+
+    fn parse_exact_topic(&mut self, value: &str) -> Result<(), String> {
+        if let Some(hash) = strip_prefix_ignore_case(value, "urn:btih:") {
+            let parsed = match hash.len() {
+                40 => InfoHash::from_hex(hash),
+                32 => base32_decode(hash).and_then(|bytes| bytes.try_into().ok()).map(InfoHash),
+                _ => None,
+            };
+            self.info_hash = Some(parsed.ok_or_else(|| format!("invalid btih `{hash}`"))?);
+        } else if let Some(hash) = strip_prefix_ignore_case(value, "urn:btmh:") {
+            let parsed = hex_decode(hash)
+                .and_then(|bytes| bytes.strip_prefix(&SHA256_MULTIHASH).map(<[u8]>::to_vec))
+                .and_then(|digest| digest.try_into().ok())
+                .map(InfoHashV2);
+            self.info_hash_v2 = Some(parsed.ok_or_else(|| format!("invalid btmh `{hash}`"))?);
+        }
+        Ok(())
+    }
+
+    /// Link for a loaded torrent, with both hashes for hybrid torrents.
+    pub fn from_metainfo(metainfo: &Metainfo) -> Self {
+        Magnet {
+            info_hash: metainfo.info_hash_v1(),
+            info_hash_v2: metainfo.info_hash_v2,
+            display_name: Some(metainfo.info.name.clone()),
+            trackers: metainfo.trackers(),
+            web_seeds: metainfo.web_seeds.clone(),
+            peers: Vec::new(),
+            select_only: Vec::new(),
+        }
+    }
+
+    /// Hashes to look the swarm up with, see [`Metainfo::swarm_hashes`].
+    pub fn swarm_hashes(&self) -> Vec<InfoHash> {
+        let mut hashes: Vec<InfoHash> = self.info_hash.into_iter().collect();
+        hashes.extend(self.info_hash_v2.map(|hash| hash.truncated()));
+        hashes
+    }
+
+    /// Whether file `index` is wanted, every file is without `so`.
+    pub fn is_selected(&self, index: usize) -> bool {
+        self.select_only.is_empty() || self.select_only.iter().any(|range| range.contains(&index))
+    }
+}
+
+impl fmt::Display for Magnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut params = Vec::new();
+        if let Some(hash) = self.info_hash {
+            params.push(format!("xt=urn:btih:{hash}"));
+        }
+        if let Some(hash) = self.info_hash_v2 {
+            params.push(format!("xt=urn:btmh:1220{hash}"));
+        }
+        if let Some(name) = &self.display_name {
+            params.push(format!("dn={}", url_encode_bytes(name.as_bytes())));
+        }
+        for (key, values) in [("tr", &self.trackers), ("ws", &self.web_seeds), ("x.pe", &self.peers)] {
+            params.extend(values.iter().map(|value| format!("{key}={}", url_encode_bytes(value.as_bytes()))));
+        }
+        if !self.select_only.is_empty() {
+            let ranges: Vec<String> = self
+                .select_only
+                .iter()
+                .map(|range| match range.start() == range.end() {
+                    true => range.start().to_string(),
+                    false => format!("{}-{}", range.start(), range.end()),
+                })
+                .collect();
+            params.push(format!("so={}", ranges.join(",")));
+        }
+        write!(f, "magnet:?{}", params.join("&"))
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(value: &'a str, prefix: &str) -> Option<&'a str> {
+    match value.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&value[prefix.len()..]),
+        _ => None,
+    }
+}
+
+/// `0,2,4-6`
+fn parse_select_only(value: &str) -> Result<Vec<RangeInclusive<usize>>, String> {
+    let number = |part: &str| part.trim().parse::<usize>().map_err(|_| format!("invalid `so` value `{value}`"));
+    value
+        .split(',')
+        .map(|part| match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (number(start)?, number(end)?);
+                match start <= end {
+                    true => Ok(start..=end),
+                    false => Err(format!("invalid `so` value `{value}`")),
+                }
+            }
+            None => number(part).map(|index| index..=index),
+        })
+        .collect()
+}
+
+/// `%XX` escapes, and `+` as a space the way form encoding writes it when
+/// `plus_as_space`.
+fn percent_decode(value: &str, plus_as_space: bool) -> Result<String, String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let byte = value
+                    .get(i + 1..i + 3)
+                    .filter(|hex| hex.bytes().all(|digit| digit.is_ascii_hexdigit()))
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("invalid percent escape in `{value}`"))?;
+                decoded.push(byte);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| format!("`{value}` is not UTF-8"))
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+/// RFC 4648 base32 without padding, as used by old btih links.
+fn base32_decode(value: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(value.len() * 5 / 8);
+    let mut buffer = 0u64;
+    let mut bits = 0;
+    for ch in value.bytes() {
+        let digit = BASE32_ALPHABET.iter().position(|&c| c == ch.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | digit as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[test]
+fn test_parse_magnet() {
+    let magnet = Magnet::parse(
+        "magnet:?xt=urn:btih:ebf84c291e1935558e41f8477de01fa4cf25456a&dn=Some+Name%21\
+         &tr=udp%3A%2F%2Ftracker.example%3A80&tr.2=http://b/announce?key=a+b&ws=http%3A%2F%2Fseed%2F\
+         &x.pe=10.0.0.1:6881&x.pe=[::1]:6881&so=0,2,4-6",
+    )
+    .unwrap();
+    assert_eq!(magnet.info_hash.unwrap().to_hex(), "ebf84c291e1935558e41f8477de01fa4cf25456a");
+    assert_eq!(magnet.display_name.as_deref(), Some("Some Name!"));
+    assert_eq!(magnet.trackers, ["udp://tracker.example:80", "http://b/announce?key=a+b"]);
+    assert_eq!(magnet.web_seeds, ["http://seed/"]);
+    assert_eq!(magnet.peers, ["10.0.0.1:6881", "[::1]:6881"]);
+    assert_eq!(magnet.select_only, vec![0..=0, 2..=2, 4..=6]);
+    assert!(magnet.is_selected(5) && !magnet.is_selected(3));
+    assert_eq!(Magnet::parse(magnet.to_string()).unwrap(), magnet);
+
+    let base32 = Magnet::parse("magnet:?xt=urn:btih:5p4eyki6de2vldsb7bdx3ya7uthskrlk").unwrap();
+    assert_eq!(base32.info_hash, magnet.info_hash);
+
+    let v2 = format!("magnet:?xt=urn:btmh:1220{}", "ab".repeat(32));
+    let v2 = Magnet::parse(v2).unwrap();
+    assert_eq!(v2.info_hash_v2, Some(InfoHashV2([0xab; 32])));
+    assert_eq!(v2.swarm_hashes(), vec![InfoHash([0xab; 20])]);
+
+    assert!(Magnet::parse("magnet:?dn=x").is_err());
+    let hash = "ebf84c291e1935558e41f8477de01fa4cf25456a";
+    assert!(Magnet::parse(format!("magnet:?xt=urn:btih:{hash}&dn=%+1")).is_err());
+    assert!(Magnet::parse("magnet:?xt=urn:btih:+bf84c291e1935558e41f8477de01fa4cf25456a").is_err());
+    assert!(Magnet::parse(format!("magnet:?xt=urn:btmh:1220+b{}", "ab".repeat(31))).is_err());
+    assert!(Magnet::parse("http://x").is_err());
+    assert!(Magnet::parse("magnet:?xt=urn:btih:1234").is_err());
+    assert!(Magnet::parse(format!("magnet:?xt=urn:btmh:1114{}", "ab".repeat(20))).is_err());
+}
+
+#[tokio::test]
+async fn test_magnet_from_metainfo() {
+    let metainfo = bencoding::read_metainfo_from_file("test.torrent").await.unwrap();
+    let magnet = Magnet::from_metainfo(&metainfo);
+    let link = magnet.to_string();
+    assert!(link.starts_with("magnet:?xt=urn:btih:ebf84c291e1935558e41f8477de01fa4cf25456a&dn="));
+    let parsed = Magnet::parse(&link).unwrap();
+    assert_eq!(parsed.info_hash, Some(metainfo.info_hash));
+    assert_eq!(parsed.trackers, metainfo.trackers());
+}
@@ -1,15 +1,32 @@
-mod network_manager;
-mod peer_messaging;
-use bencoding::read_torrent_from_file;
-use tokio::io;
-use tokio::net::TcpStream;
+mod make_torrent;
+
+use bencoding::read_metainfo_from_file;
+use console_torrent::magnet::Magnet;
 
 #[tokio::main]
 async fn main() {
-    println!("Hello world!");
-    let torrent_data = read_torrent_from_file("test.torrent").await.expect("Err");
-
-    //println!("{:?}", torrent_data)
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("make-torrent") {
+        if let Err(err) = make_torrent::run(&args[1..]) {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    // A magnet link or a .torrent file to start from.
+    let source = args.first().map(String::as_str).unwrap_or("test.torrent");
+    let magnet = if source.starts_with("magnet:") {
+        Magnet::parse(source)
+    } else {
+        read_metainfo_from_file(source).await.map(|metainfo| Magnet::from_metainfo(&metainfo))
+    };
+    match magnet {
+        Ok(magnet) => println!("{magnet}"),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    }
 }
 
 #[tokio::test]
@@ -0,0 +1,151 @@
+use bencoding::{Bencode, CreatedTorrent, MetaVersion, TorrentCreator};
+
+const USAGE: &str = "usage: make-torrent <path> [-o <file>] [-a <url>[,<url>...]]... [-w <url>]... \
+[-c <comment>] [-l <piece length>] [-s <source>] [-t <threads>] [-p] [--no-date] [--v2 | --hybrid]";
+
+/// `make-torrent` subcommand. Every `-a` adds a tracker tier, urls of one
+/// tier are separated by commas. Without `-o` the torrent is written to
+/// `<name>.torrent` in the current directory.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = parse_args(args)?;
+    let path = options.path;
+    let mut creator = TorrentCreator::new(&path).private(options.private).version(options.version);
+    for tier in &options.tiers {
+        creator = creator.tracker_tier(tier.split(',').filter(|url| !url.is_empty()));
+    }
+    for seed in options.web_seeds {
+        creator = creator.web_seed(seed);
+    }
+    if let Some(comment) = options.comment {
+        creator = creator.comment(comment);
+    }
+    if let Some(piece_length) = options.piece_length {
+        creator = creator.piece_length(piece_length);
+    }
+    if let Some(source) = options.source {
+        creator = creator.source(source);
+    }
+    if let Some(threads) = options.threads {
+        creator = creator.threads(threads);
+    }
+    if options.no_date {
+        creator = creator.creation_date(None);
+    }
+    let created = creator.create().map_err(|err| format!("{path}: {err}"))?;
+    let output = options.output.unwrap_or_else(|| default_output(&created));
+    created.write_to_file(&output).map_err(|err| format!("{output}: {err}"))?;
+    println!("{output}: info-hash {}", created.info_hash);
+    Ok(())
+}
+
+/// Arguments of `make-torrent`.
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    path: String,
+    output: Option<String>,
+    tiers: Vec<String>,
+    web_seeds: Vec<String>,
+    comment: Option<String>,
+    piece_length: Option<u64>,
+    source: Option<String>,
+    threads: Option<usize>,
+    private: bool,
+    no_date: bool,
+    version: MetaVersion,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut args = args.iter();
+    let mut path = None;
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| format!("{arg} needs a value\n{USAGE}"));
+        match arg.as_str() {
+            "-o" | "--output" => options.output = Some(value()?),
+            "-a" | "--announce" => options.tiers.push(value()?),
+            "-w" | "--web-seed" => options.web_seeds.push(value()?),
+            "-c" | "--comment" => options.comment = Some(value()?),
+            "-l" | "--piece-length" => options.piece_length = Some(parse_number(arg, &value()?)?),
+            "-s" | "--source" => options.source = Some(value()?),
+            "-t" | "--threads" => options.threads = Some(parse_threads(arg, &value()?)?),
+            "-p" | "--private" => options.private = true,
+            "--no-date" => options.no_date = true,
+            "--v2" => options.version = MetaVersion::V2,
+            "--hybrid" => options.version = MetaVersion::Hybrid,
+            _ if arg.starts_with('-') || path.is_some() => return Err(format!("unexpected argument {arg}\n{USAGE}")),
+            _ => path = Some(arg.clone()),
+        }
+    }
+    options.path = path.ok_or_else(|| USAGE.to_string())?;
+    Ok(options)
+}
+
+/// `<name>.torrent`, named after `info.name` rather than the argument so
+/// `make-torrent .` doesn't write `..torrent`.
+fn default_output(created: &CreatedTorrent) -> String {
+    let name = created.torrent.get("info").and_then(|info| info.get("name")).and_then(Bencode::as_str);
+    format!("{}.torrent", name.unwrap_or("torrent"))
+}
+
+fn parse_threads(name: &str, value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err(format!("{name}: needs at least one thread")),
+        Ok(threads) => Ok(threads),
+        Err(_) => Err(format!("{name}: `{value}` is not a number")),
+    }
+}
+
+/// Accepts plain byte counts and `k`/`m` suffixes, `-l 256k`.
+fn parse_number(name: &str, value: &str) -> Result<u64, String> {
+    let lower = value.to_ascii_lowercase();
+    let (digits, multiplier) = match lower.strip_suffix('k') {
+        Some(digits) => (digits, 1024),
+        None => match lower.strip_suffix('m') {
+            Some(digits) => (digits, 1024 * 1024),
+            None => (lower.as_str(), 1),
+        },
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("{name}: `{value}` is not a number"))
+}
+
+#[test]
+fn test_parse_args() {
+    let args = |line: &str| parse_args(&line.split_whitespace().map(str::to_string).collect::<Vec<_>>());
+    assert_eq!(
+        args("dir -a a,b -a c -l 256k -t 4 -p --no-date -o out.torrent"),
+        Ok(Options {
+            path: "dir".to_string(),
+            output: Some("out.torrent".to_string()),
+            tiers: vec!["a,b".to_string(), "c".to_string()],
+            piece_length: Some(256 * 1024),
+            threads: Some(4),
+            private: true,
+            no_date: true,
+            ..Options::default()
+        })
+    );
+    assert_eq!(args("dir -l 1m").unwrap().piece_length, Some(1 << 20));
+    assert_eq!(args("dir --hybrid").unwrap().version, MetaVersion::Hybrid);
+    assert_eq!(args("dir -t 4k"), Err("-t: `4k` is not a number".to_string()));
+    assert_eq!(args("dir -t 0"), Err("-t: needs at least one thread".to_string()));
+    assert!(args("dir -l 12x").unwrap_err().starts_with("-l: `12x` is not a number"));
+    assert!(args("dir -c").unwrap_err().starts_with("-c needs a value\n"));
+    assert!(args("dir other").unwrap_err().starts_with("unexpected argument other\n"));
+    assert!(args("dir --bogus").unwrap_err().starts_with("unexpected argument --bogus\n"));
+    assert_eq!(args("-p"), Err(USAGE.to_string()));
+}
+
+#[test]
+fn test_default_output() {
+    let root = std::env::temp_dir().join(format!("make-torrent-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("data")).unwrap();
+    std::fs::write(root.join("data/a.bin"), [1; 100]).unwrap();
+    let created = TorrentCreator::new(root.join("data/.")).create().unwrap();
+    assert_eq!(default_output(&created), "data.torrent");
+    std::fs::remove_dir_all(&root).unwrap();
+}
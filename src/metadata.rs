@@ -0,0 +1,282 @@
+use bencoding::stream::StreamDecoder;
+use bencoding::{decode_bencode, decode_bencode_with_info_span, Bencode, DecodeOptions, InfoHash, Metainfo};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::magnet::Magnet;
+
+/// Name of the extension in the `m` dictionary of the extension handshake.
+pub const EXTENSION_NAME: &str = "ut_metadata";
+pub const METADATA_PIECE_SIZE: usize = 16 * 1024;
+/// Larger `metadata_size` values are refused, real info dictionaries stay
+/// far below this.
+pub const MAX_METADATA_SIZE: usize = 8 * 1024 * 1024;
+
+/// `ut_metadata` message, the payload after the extended message id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataMessage {
+    Request { piece: u32 },
+    Data { piece: u32, total_size: usize, data: Vec<u8> },
+    Reject { piece: u32 },
+}
+
+impl MetadataMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (msg_type, piece) = match self {
+            MetadataMessage::Request { piece } => (0, piece),
+            MetadataMessage::Data { piece, .. } => (1, piece),
+            MetadataMessage::Reject { piece } => (2, piece),
+        };
+        let mut header = Bencode::Dictionary(Vec::new());
+        header.insert("msg_type", Bencode::Integer(msg_type)).unwrap();
+        header.insert("piece", Bencode::Integer(*piece as i64)).unwrap();
+        if let MetadataMessage::Data { total_size, .. } = self {
+            header.insert("total_size", Bencode::Integer(*total_size as i64)).unwrap();
+        }
+        let mut bytes = header.to_bencode_bytes().unwrap();
+        if let MetadataMessage::Data { data, .. } = self {
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    /// The bencoded header is followed by the raw piece in `data` messages,
+    /// so only the first value is decoded.
+    pub fn from_bytes(payload: &[u8]) -> Result<Self, String> {
+        let mut decoder = StreamDecoder::with_options(DecodeOptions::lenient().max_length(1024));
+        let feed = decoder.feed(payload).map_err(|err| format!("invalid ut_metadata message: {err}"))?;
+        let header = feed.value.ok_or("truncated ut_metadata message")?;
+        let field = |key: &str| header.get(key).and_then(Bencode::as_int);
+        let piece = field("piece")
+            .and_then(|piece| u32::try_from(piece).ok())
+            .ok_or("ut_metadata message without a valid piece")?;
+        match field("msg_type") {
+            Some(0) => Ok(MetadataMessage::Request { piece }),
+            Some(1) => {
+                let total_size = field("total_size")
+                    .and_then(|size| usize::try_from(size).ok())
+                    .ok_or("ut_metadata data without total_size")?;
+                Ok(MetadataMessage::Data { piece, total_size, data: payload[feed.consumed..].to_vec() })
+            }
+            Some(2) => Ok(MetadataMessage::Reject { piece }),
+            other => Err(format!("unknown ut_metadata msg_type {other:?}")),
+        }
+    }
+}
+
+/// Our `ut_metadata` id and the `metadata_size` from a peer's extension
+/// handshake, `None` when the peer does not support the extension.
+pub fn from_extension_handshake(handshake: &Bencode) -> Option<(u8, Option<usize>)> {
+    let id = handshake.get("m")?.get(EXTENSION_NAME)?.as_int()?;
+    // Id 0 means the peer disabled the extension.
+    let id = u8::try_from(id).ok().filter(|id| *id != 0)?;
+    let size = handshake.get("metadata_size").and_then(Bencode::as_int).and_then(|size| usize::try_from(size).ok());
+    Some((id, size))
+}
+
+fn piece_count(total_size: usize) -> usize {
+    total_size.div_ceil(METADATA_PIECE_SIZE)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PieceState {
+    Missing,
+    Requested,
+    Received,
+}
+
+/// Collects the pieces of the `info` dictionary from peers and checks the
+/// result against the info-hash of the swarm.
+pub struct MetadataAssembler {
+    info_hash: InfoHash,
+    buffer: Vec<u8>,
+    pieces: Vec<PieceState>,
+}
+
+impl MetadataAssembler {
+    /// `total_size` is the `metadata_size` a peer announced.
+    pub fn new(info_hash: InfoHash, total_size: usize) -> Result<Self, String> {
+        if total_size == 0 || total_size > MAX_METADATA_SIZE {
+            return Err(format!("metadata size {total_size} is out of range"));
+        }
+        Ok(MetadataAssembler {
+            info_hash,
+            buffer: vec![0; total_size],
+            pieces: vec![PieceState::Missing; piece_count(total_size)],
+        })
+    }
+
+    /// Next piece nobody was asked for yet, marked as requested.
+    pub fn next_request(&mut self) -> Option<MetadataMessage> {
+        let piece = self.pieces.iter().position(|state| *state == PieceState::Missing)?;
+        self.pieces[piece] = PieceState::Requested;
+        Some(MetadataMessage::Request { piece: piece as u32 })
+    }
+
+    /// Puts a rejected or timed out request back so another peer gets it.
+    pub fn release(&mut self, piece: u32) {
+        if let Some(state) = self.pieces.get_mut(piece as usize) {
+            if *state == PieceState::Requested {
+                *state = PieceState::Missing;
+            }
+        }
+    }
+
+    /// Handles a message from a peer. Returns the verified `info` bytes once
+    /// the last piece arrived. A hash mismatch starts over from scratch,
+    /// the caller should stop trusting the peers involved.
+    pub fn on_message(&mut self, message: &MetadataMessage) -> Result<Option<Vec<u8>>, String> {
+        let (piece, total_size, data) = match message {
+            MetadataMessage::Data { piece, total_size, data } => (*piece as usize, *total_size, data),
+            MetadataMessage::Reject { piece } => {
+                self.release(*piece);
+                return Ok(None);
+            }
+            MetadataMessage::Request { .. } => return Ok(None),
+        };
+        if total_size != self.buffer.len() {
+            return Err(format!("peer sent total_size {total_size}, expected {}", self.buffer.len()));
+        }
+        let start = piece * METADATA_PIECE_SIZE;
+        let expected_len = match self.pieces.get(piece) {
+            Some(_) => (self.buffer.len() - start).min(METADATA_PIECE_SIZE),
+            None => return Err(format!("metadata piece {piece} is out of range")),
+        };
+        if data.len() != expected_len {
+            return Err(format!("metadata piece {piece} has {} bytes, expected {expected_len}", data.len()));
+        }
+        self.buffer[start..start + expected_len].copy_from_slice(data);
+        self.pieces[piece] = PieceState::Received;
+        if self.pieces.iter().any(|state| *state != PieceState::Received) {
+            return Ok(None);
+        }
+        if !matches_info_hash(&self.buffer, &self.info_hash) {
+            self.pieces.fill(PieceState::Missing);
+            return Err("metadata does not match the info-hash".to_string());
+        }
+        Ok(Some(self.buffer.clone()))
+    }
+}
+
+/// v1 swarms use the SHA-1 of the info dictionary, v2 swarms the SHA-256
+/// truncated to 20 bytes.
+fn matches_info_hash(info: &[u8], info_hash: &InfoHash) -> bool {
+    let sha1: [u8; 20] = Sha1::digest(info).into();
+    sha1 == info_hash.0 || Sha256::digest(info)[..20] == info_hash.0
+}
+
+/// Turns downloaded `info` bytes into a torrent, with the trackers and web
+/// seeds of the magnet link.
+pub fn metainfo_from_magnet(magnet: &Magnet, info_bytes: &[u8]) -> Result<Metainfo, String> {
+    let info = decode_bencode(info_bytes).map_err(|err| format!("metadata is not valid bencode: {err}"))?;
+    let mut root = Bencode::Dictionary(Vec::new());
+    if let Some(tracker) = magnet.trackers.first() {
+        root.insert("announce", tracker.as_str().into()).unwrap();
+        let tiers = magnet.trackers.iter().map(|url| Bencode::List(vec![url.as_str().into()])).collect();
+        root.insert("announce-list", Bencode::List(tiers)).unwrap();
+    }
+    if !magnet.web_seeds.is_empty() {
+        let seeds = magnet.web_seeds.iter().map(|url| url.as_str().into()).collect();
+        root.insert("url-list", Bencode::List(seeds)).unwrap();
+    }
+    root.insert("info", info).unwrap();
+    Metainfo::from_bencode(&root, info_bytes).map_err(|err| err.to_string())
+}
+
+/// Answers `ut_metadata` requests with the raw `info` dictionary of a
+/// torrent we have.
+pub struct MetadataServer {
+    info_bytes: Vec<u8>,
+}
+
+impl MetadataServer {
+    pub fn new(info_bytes: Vec<u8>) -> Self {
+        MetadataServer { info_bytes }
+    }
+
+    /// Takes the exact `info` span of a `.torrent` file, re-encoding could
+    /// change the info-hash.
+    pub fn from_torrent_bytes(data: &[u8]) -> Result<Self, String> {
+        match decode_bencode_with_info_span(data, &DecodeOptions::default()) {
+            Ok((_, Some(span))) => Ok(MetadataServer::new(data[span].to_vec())),
+            Ok((_, None)) => Err("torrent has no info dictionary".to_string()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    /// `metadata_size` for our extension handshake.
+    pub fn metadata_size(&self) -> usize {
+        self.info_bytes.len()
+    }
+
+    pub fn handle(&self, message: &MetadataMessage) -> Option<MetadataMessage> {
+        let piece = match message {
+            MetadataMessage::Request { piece } => *piece,
+            _ => return None,
+        };
+        let start = piece as usize * METADATA_PIECE_SIZE;
+        if start >= self.info_bytes.len() {
+            return Some(MetadataMessage::Reject { piece });
+        }
+        let end = (start + METADATA_PIECE_SIZE).min(self.info_bytes.len());
+        Some(MetadataMessage::Data {
+            piece,
+            total_size: self.info_bytes.len(),
+            data: self.info_bytes[start..end].to_vec(),
+        })
+    }
+}
+
+#[test]
+fn test_message_encoding() {
+    let data = MetadataMessage::Data { piece: 1, total_size: 20000, data: b"d4:infoe".to_vec() };
+    let bytes = data.to_bytes();
+    assert_eq!(bytes, b"d8:msg_typei1e5:piecei1e10:total_sizei20000eed4:infoe");
+    assert_eq!(MetadataMessage::from_bytes(&bytes), Ok(data));
+    assert_eq!(
+        MetadataMessage::from_bytes(b"d8:msg_typei0e5:piecei0ee"),
+        Ok(MetadataMessage::Request { piece: 0 })
+    );
+    assert!(MetadataMessage::from_bytes(b"d8:msg_typei7e5:piecei0ee").is_err());
+    assert!(MetadataMessage::from_bytes(b"d8:msg_typei0e").is_err());
+
+    let handshake = decode_bencode(b"d1:md11:ut_metadatai3ee13:metadata_sizei31235ee").unwrap();
+    assert_eq!(from_extension_handshake(&handshake), Some((3, Some(31235))));
+}
+
+#[tokio::test]
+async fn test_fetch_metadata_from_server() {
+    let torrent = tokio::fs::read("test.torrent").await.unwrap();
+    let metainfo = Metainfo::from_bytes(&torrent).unwrap();
+    let server = MetadataServer::from_torrent_bytes(&torrent).unwrap();
+    let magnet = Magnet::parse(Magnet::from_metainfo(&metainfo).to_string()).unwrap();
+
+    let mut assembler = MetadataAssembler::new(magnet.info_hash.unwrap(), server.metadata_size()).unwrap();
+    let first = assembler.next_request().unwrap();
+    // A rejected piece is handed out again.
+    assert_eq!(assembler.on_message(&MetadataMessage::Reject { piece: 0 }), Ok(None));
+    assert_eq!(assembler.next_request(), Some(first));
+    let mut info_bytes = None;
+    let mut requests = vec![MetadataMessage::Request { piece: 0 }];
+    requests.extend(std::iter::from_fn(|| assembler.next_request()));
+    for request in requests {
+        let reply = server.handle(&MetadataMessage::from_bytes(&request.to_bytes()).unwrap()).unwrap();
+        if let Some(bytes) = assembler.on_message(&MetadataMessage::from_bytes(&reply.to_bytes()).unwrap()).unwrap() {
+            info_bytes = Some(bytes);
+        }
+    }
+    let fetched = metainfo_from_magnet(&magnet, &info_bytes.unwrap()).unwrap();
+    assert_eq!(fetched.info, metainfo.info);
+    assert_eq!(fetched.info_hash, metainfo.info_hash);
+    assert_eq!(fetched.trackers(), metainfo.trackers());
+
+    assert_eq!(
+        server.handle(&MetadataMessage::Request { piece: 1000 }),
+        Some(MetadataMessage::Reject { piece: 1000 })
+    );
+    let mut wrong = MetadataAssembler::new(InfoHash([0; 20]), 10).unwrap();
+    wrong.next_request();
+    let data = MetadataMessage::Data { piece: 0, total_size: 10, data: vec![b'x'; 10] };
+    assert!(wrong.on_message(&data).is_err());
+    assert!(wrong.next_request().is_some());
+}
@@ -1,7 +1,357 @@
-struct NetworkManager {}
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use bencoding::info_hash::url_encode_bytes;
+use bencoding::{decode_bencode_with_options, Bencode, DecodeOptions, InfoHash};
+use reqwest::redirect::Policy;
+
+use crate::peer_messaging::{AnnounceEventType, ScrapeResult};
+
+/// Redirects followed before a tracker is given up on.
+const MAX_REDIRECTS: usize = 10;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Query parameters of an HTTP announce (BEP 3, BEP 23).
+#[derive(Debug, Clone)]
+pub struct HttpAnnounceRequest {
+    pub info_hash: InfoHash,
+    pub peer_id: [u8; 20],
+    pub port: u16,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub left: u64,
+    pub event: AnnounceEventType,
+    pub num_want: Option<u32>,
+    pub key: Option<u32>,
+    /// `tracker id` of the previous response, sent back as `trackerid`.
+    pub tracker_id: Option<String>,
+}
+
+impl HttpAnnounceRequest {
+    /// `url` with the announce parameters appended. The binary
+    /// `info_hash` and `peer_id` are percent-encoded byte by byte.
+    pub fn to_url(&self, url: &str) -> String {
+        let mut params = vec![
+            format!("info_hash={}", url_encode_bytes(self.info_hash.as_bytes())),
+            format!("peer_id={}", url_encode_bytes(&self.peer_id)),
+            format!("port={}", self.port),
+            format!("uploaded={}", self.uploaded),
+            format!("downloaded={}", self.downloaded),
+            format!("left={}", self.left),
+            "compact=1".to_string(),
+        ];
+        let event = match self.event {
+            AnnounceEventType::UNDEFINED => None,
+            AnnounceEventType::COMPLETED => Some("completed"),
+            AnnounceEventType::STARTED => Some("started"),
+            AnnounceEventType::STOPPED => Some("stopped"),
+        };
+        params.extend(event.map(|event| format!("event={event}")));
+        params.extend(self.num_want.map(|num_want| format!("numwant={num_want}")));
+        params.extend(self.key.map(|key| format!("key={key:08x}")));
+        params.extend(self.tracker_id.as_ref().map(|id| format!("trackerid={}", url_encode_bytes(id.as_bytes()))));
+        append_query(url, &params)
+    }
+}
+
+/// Successful HTTP announce, a `failure reason` is returned as an error.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpAnnounceResponse {
+    pub interval: u32,
+    pub min_interval: Option<u32>,
+    pub tracker_id: Option<String>,
+    pub warning_message: Option<String>,
+    /// Seeders.
+    pub complete: Option<u32>,
+    /// Leechers.
+    pub incomplete: Option<u32>,
+    /// `peers` in compact or dictionary form followed by `peers6`.
+    pub peers: Vec<SocketAddr>,
+}
+
+impl HttpAnnounceResponse {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let response = decode_response(bytes)?;
+        let int = |key: &str| int_field(&response, key);
+        let text = |key: &str| response.get(key).and_then(Bencode::as_bytes).map(String::from_utf8_lossy);
+        let mut peers = match response.get("peers") {
+            Some(Bencode::List(peers)) => peers.iter().filter_map(dictionary_peer).collect(),
+            Some(peers) => compact_peers(peers.as_bytes().unwrap_or_default(), 4),
+            None => Vec::new(),
+        };
+        if let Some(peers6) = response.get("peers6").and_then(Bencode::as_bytes) {
+            peers.extend(compact_peers(peers6, 16));
+        }
+        Ok(HttpAnnounceResponse {
+            interval: int("interval").ok_or("announce response without interval")?,
+            min_interval: int("min interval"),
+            tracker_id: text("tracker id").map(String::from),
+            warning_message: text("warning message").map(String::from),
+            complete: int("complete"),
+            incomplete: int("incomplete"),
+            peers,
+        })
+    }
+}
+
+/// HTTP and HTTPS tracker client.
+pub struct NetworkManager {
+    client: reqwest::Client,
+}
 
 impl NetworkManager {
-    async fn make_request() {
-        todo!()
+    pub fn new() -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .redirect(Policy::limited(MAX_REDIRECTS))
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|err| err.to_string())?;
+        Ok(NetworkManager { client })
+    }
+
+    pub async fn announce(&self, url: &str, request: &HttpAnnounceRequest) -> Result<HttpAnnounceResponse, String> {
+        let body = self.make_request(&request.to_url(url)).await?;
+        HttpAnnounceResponse::from_bytes(&body)
+    }
+
+    /// Scrape through the scrape url derived from the announce `url`.
+    /// Torrents the tracker does not know are missing from the result.
+    pub async fn scrape(&self, url: &str, info_hashes: &[InfoHash]) -> Result<Vec<(InfoHash, ScrapeResult)>, String> {
+        let scrape_url = scrape_url(url).ok_or_else(|| format!("{url} does not support scrape"))?;
+        let params: Vec<String> = info_hashes
+            .iter()
+            .map(|info_hash| format!("info_hash={}", url_encode_bytes(info_hash.as_bytes())))
+            .collect();
+        let body = self.make_request(&append_query(&scrape_url, &params)).await?;
+        parse_scrape_response(&body)
+    }
+
+    /// GET `url` and return the body, redirects are followed.
+    async fn make_request(&self, url: &str) -> Result<Vec<u8>, String> {
+        let response = self.client.get(url).send().await.map_err(|err| err.to_string())?;
+        let status = response.status();
+        let body = response.bytes().await.map_err(|err| err.to_string())?;
+        if !status.is_success() {
+            // Some trackers send `failure reason` with an error status, it
+            // says more than the status does.
+            let decoded = decode_bencode_with_options(&body, &DecodeOptions::lenient()).ok();
+            let reason = decoded.as_ref().and_then(failure_reason);
+            return Err(reason.unwrap_or_else(|| format!("Tracker answered {status}")));
+        }
+        Ok(body.to_vec())
     }
 }
+
+fn append_query(url: &str, params: &[String]) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{url}{separator}{}", params.join("&"))
+}
+
+/// By convention the last path segment `announce` is replaced with
+/// `scrape`, trackers without that segment cannot be scraped.
+pub fn scrape_url(announce: &str) -> Option<String> {
+    let (path, query) = announce.split_once('?').map_or((announce, None), |(path, query)| (path, Some(query)));
+    let (base, last) = path.rsplit_once('/')?;
+    let rest = last.strip_prefix("announce")?;
+    let mut url = format!("{base}/scrape{rest}");
+    if let Some(query) = query {
+        url.push('?');
+        url.push_str(query);
+    }
+    Some(url)
+}
+
+/// Decodes a tracker response dictionary, `failure reason` becomes the
+/// error.
+fn decode_response(bytes: &[u8]) -> Result<Bencode, String> {
+    let response = decode_bencode_with_options(bytes, &DecodeOptions::lenient())
+        .map_err(|err| format!("invalid tracker response: {err}"))?;
+    if response.as_dict().is_none() {
+        return Err("tracker response is not a dictionary".to_string());
+    }
+    match failure_reason(&response) {
+        Some(reason) => Err(reason),
+        None => Ok(response),
+    }
+}
+
+fn int_field(dict: &Bencode, key: &str) -> Option<u32> {
+    dict.get(key).and_then(Bencode::as_int).and_then(|value| u32::try_from(value).ok())
+}
+
+fn failure_reason(response: &Bencode) -> Option<String> {
+    let reason = response.get("failure reason")?.as_bytes()?;
+    Some(format!("Tracker failure: {}", String::from_utf8_lossy(reason)))
+}
+
+fn parse_scrape_response(bytes: &[u8]) -> Result<Vec<(InfoHash, ScrapeResult)>, String> {
+    let response = decode_response(bytes)?;
+    let files = response.get("files").and_then(Bencode::as_dict).ok_or("scrape response without files")?;
+    let mut results = Vec::with_capacity(files.len());
+    for (key, stats) in files {
+        let info_hash = key
+            .as_bytes()
+            .and_then(|key| <[u8; 20]>::try_from(key).ok())
+            .ok_or("scrape response with an invalid info-hash")?;
+        let int = |key: &str| int_field(stats, key).unwrap_or(0);
+        results.push((
+            InfoHash(info_hash),
+            ScrapeResult { seeders: int("complete"), completed: int("downloaded"), leechers: int("incomplete") },
+        ));
+    }
+    Ok(results)
+}
+
+/// Compact peers of `ip_len` address bytes followed by a port.
+pub(crate) fn compact_peers(bytes: &[u8], ip_len: usize) -> Vec<SocketAddr> {
+    bytes
+        .chunks_exact(ip_len + 2)
+        .map(|peer| {
+            let ip = match ip_len {
+                4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(&peer[..4]).unwrap())),
+                _ => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&peer[..16]).unwrap())),
+            };
+            SocketAddr::new(ip, u16::from_be_bytes([peer[ip_len], peer[ip_len + 1]]))
+        })
+        .collect()
+}
+
+/// `{ip, port, peer id}` entry of a non-compact peer list. Hostnames are
+/// skipped, peers are dialed by address only.
+fn dictionary_peer(peer: &Bencode) -> Option<SocketAddr> {
+    let ip = peer.get("ip")?.as_str()?.parse::<IpAddr>().ok()?;
+    let port = u16::try_from(peer.get("port")?.as_int()?).ok()?;
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Serves canned HTTP responses on localhost, `respond` gets the request
+/// target and returns the status line and body.
+#[cfg(test)]
+pub(crate) async fn spawn_fake_http_tracker<F>(respond: F) -> SocketAddr
+where
+    F: Fn(&str) -> (String, Vec<u8>) + Send + Sync + 'static,
+{
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => request.extend_from_slice(&buf[..len]),
+                }
+            }
+            let request = String::from_utf8_lossy(&request).into_owned();
+            let target = request.split(' ').nth(1).unwrap_or_default();
+            let (status, body) = respond(target);
+            let head = format!("HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+            let _ = stream.write_all(head.as_bytes()).await;
+            let _ = stream.write_all(&body).await;
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_http_announce() {
+    let tracker = spawn_fake_http_tracker(|target| {
+        if let Some(query) = target.strip_prefix("/old/announce?") {
+            return (format!("302 Found\r\nLocation: /announce?passkey=abc&{query}"), Vec::new());
+        }
+        let info_hash = format!("info_hash=%00%01%02AB{}%FF", "%00".repeat(14));
+        assert!(target.starts_with(&format!("/announce?passkey=abc&{info_hash}&")));
+        assert!(target.contains("&peer_id=-CT0100-%20~xxxxxxxxxx&"));
+        assert!(target.contains("&event=started&numwant=50"));
+        let mut body = b"d8:completei5e10:incompletei3e8:intervali1800e12:min intervali60e5:peers12:".to_vec();
+        body.extend_from_slice(&[10, 0, 0, 1, 0x1a, 0xe1, 192, 168, 1, 2, 0, 80]);
+        body.extend_from_slice(b"6:peers618:");
+        body.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x1a, 0xe1]);
+        body.extend_from_slice(b"10:tracker id3:xyz15:warning message4:slowe");
+        ("200 OK".to_string(), body)
+    })
+    .await;
+
+    let mut info_hash = [0; 20];
+    info_hash[..5].copy_from_slice(&[0, 1, 2, b'A', b'B']);
+    info_hash[19] = 0xff;
+    let mut peer_id = [b'x'; 20];
+    peer_id[..10].copy_from_slice(b"-CT0100- ~");
+    let request = HttpAnnounceRequest {
+        info_hash: InfoHash(info_hash),
+        peer_id,
+        port: 6881,
+        uploaded: 0,
+        downloaded: 0,
+        left: 100,
+        event: AnnounceEventType::STARTED,
+        num_want: Some(50),
+        key: None,
+        tracker_id: None,
+    };
+    let manager = NetworkManager::new().unwrap();
+    let response = manager.announce(&format!("http://{tracker}/old/announce"), &request).await.unwrap();
+    assert_eq!(response.interval, 1800);
+    assert_eq!(response.min_interval, Some(60));
+    assert_eq!((response.complete, response.incomplete), (Some(5), Some(3)));
+    assert_eq!(response.tracker_id.as_deref(), Some("xyz"));
+    assert_eq!(response.warning_message.as_deref(), Some("slow"));
+    assert_eq!(
+        response.peers,
+        vec![
+            "10.0.0.1:6881".parse::<SocketAddr>().unwrap(),
+            "192.168.1.2:80".parse().unwrap(),
+            "[2001:db8::1]:6881".parse().unwrap()
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_http_scrape_and_failures() {
+    let tracker = spawn_fake_http_tracker(|target| match target.split('?').next().unwrap() {
+        "/scrape" => {
+            assert_eq!(target, format!("/scrape?info_hash={}&info_hash={}", "%01".repeat(20), "%02".repeat(20)));
+            let mut body = b"d5:filesd20:".to_vec();
+            body.extend_from_slice(&[1; 20]);
+            body.extend_from_slice(b"d8:completei4e10:downloadedi9e10:incompletei2eeee");
+            ("200 OK".to_string(), body)
+        }
+        "/failing/announce" => ("400 Bad Request".to_string(), b"d14:failure reason11:unknown keye".to_vec()),
+        _ => ("404 Not Found".to_string(), b"not here".to_vec()),
+    })
+    .await;
+
+    let manager = NetworkManager::new().unwrap();
+    let results = manager
+        .scrape(&format!("http://{tracker}/announce"), &[InfoHash([1; 20]), InfoHash([2; 20])])
+        .await
+        .unwrap();
+    assert_eq!(results, vec![(InfoHash([1; 20]), ScrapeResult { seeders: 4, completed: 9, leechers: 2 })]);
+
+    let request = HttpAnnounceRequest {
+        info_hash: InfoHash([1; 20]),
+        peer_id: [b'x'; 20],
+        port: 6881,
+        uploaded: 0,
+        downloaded: 0,
+        left: 0,
+        event: AnnounceEventType::UNDEFINED,
+        num_want: None,
+        key: None,
+        tracker_id: None,
+    };
+    let err = manager.announce(&format!("http://{tracker}/failing/announce"), &request).await.unwrap_err();
+    assert_eq!(err, "Tracker failure: unknown key");
+    assert!(manager.announce(&format!("http://{tracker}/other"), &request).await.is_err());
+
+    let dict_peers = b"d8:intervali60e5:peersld2:ip8:10.0.0.27:peer id20:xxxxxxxxxxxxxxxxxxxx4:porti51413ee\
+        d2:ip7:a.b.com4:porti1eeee";
+    let response = HttpAnnounceResponse::from_bytes(dict_peers).unwrap();
+    assert_eq!(response.peers, vec!["10.0.0.2:51413".parse::<SocketAddr>().unwrap()]);
+
+    assert_eq!(scrape_url("http://t/x/announce.php?k=1").as_deref(), Some("http://t/x/scrape.php?k=1"));
+    assert_eq!(scrape_url("http://t/a"), None);
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+
+use bencoding::Bencode;
+use rand::Rng;
+
+/// Azureus style client code of this client.
+pub const CLIENT_CODE: &str = "CT";
+pub const CLIENT_NAME: &str = "ConsoleTorrent";
+/// Characters of the random part, printable so ids stay readable in
+/// tracker logs.
+const RANDOM_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Azureus style client codes, `-XX1234-`.
+const AZUREUS_CLIENTS: &[(&str, &str)] = &[
+    ("AZ", "Vuze"),
+    ("BC", "BitComet"),
+    ("BI", "BiglyBT"),
+    ("BT", "BitTorrent"),
+    ("CT", CLIENT_NAME),
+    ("DE", "Deluge"),
+    ("FD", "Free Download Manager"),
+    ("KT", "KTorrent"),
+    ("LT", "libtorrent"),
+    ("lt", "rTorrent"),
+    ("qB", "qBittorrent"),
+    ("SD", "Thunder"),
+    ("TR", "Transmission"),
+    ("UM", "µTorrent Mac"),
+    ("UT", "µTorrent"),
+    ("WW", "WebTorrent"),
+    ("XL", "Xunlei"),
+];
+
+/// `-CT0100-` for version 0.1.0, one character per version component and
+/// a build digit.
+pub fn client_prefix() -> String {
+    let mut digits: Vec<char> = env!("CARGO_PKG_VERSION")
+        .split(['.', '-'])
+        .take(3)
+        .map(|component| component.parse::<u32>().ok().and_then(|n| char::from_digit(n, 36)).unwrap_or('0'))
+        .map(|digit| digit.to_ascii_uppercase())
+        .collect();
+    digits.resize(4, '0');
+    format!("-{CLIENT_CODE}{}-", digits.into_iter().collect::<String>())
+}
+
+/// `v` of our extension handshake.
+pub fn client_version() -> String {
+    format!("{CLIENT_NAME} {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// New peer id: the client prefix and 12 random characters.
+pub fn generate() -> [u8; 20] {
+    let mut peer_id = [0; 20];
+    let prefix = client_prefix();
+    peer_id[..prefix.len()].copy_from_slice(prefix.as_bytes());
+    let mut rng = rand::thread_rng();
+    for byte in &mut peer_id[prefix.len()..] {
+        *byte = RANDOM_ALPHABET[rng.gen_range(0..RANDOM_ALPHABET.len())];
+    }
+    peer_id
+}
+
+/// Client of a peer, for display and per-client policies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl fmt::Display for ClientInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} {version}", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl ClientInfo {
+    /// Identifies Azureus style (`-qB4630-`) and Mainline style
+    /// (`M7-4-3--`) peer ids.
+    pub fn from_peer_id(peer_id: &[u8; 20]) -> Option<Self> {
+        if peer_id[0] == b'-' && peer_id[7] == b'-' {
+            let code = std::str::from_utf8(&peer_id[1..3]).ok()?;
+            let (_, name) = AZUREUS_CLIENTS.iter().find(|(known, _)| *known == code)?;
+            return Some(ClientInfo { name: name.to_string(), version: azureus_version(&peer_id[3..7]) });
+        }
+        if peer_id[0] == b'M' {
+            let end = peer_id.windows(2).position(|pair| pair == b"--")?;
+            let version = std::str::from_utf8(&peer_id[1..end]).ok()?;
+            if version.split('-').all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit())) {
+                return Some(ClientInfo { name: "Mainline".to_string(), version: Some(version.replace('-', ".")) });
+            }
+        }
+        None
+    }
+
+    /// The `v` field of an extension handshake, `qBittorrent/4.6.3` or
+    /// `Transmission 4.0.5`.
+    pub fn from_extension_handshake(handshake: &Bencode) -> Option<Self> {
+        let v = handshake.get("v")?.as_str()?.trim();
+        if v.is_empty() {
+            return None;
+        }
+        let (name, version) = match v.rsplit_once(['/', ' ']) {
+            Some((name, version)) if version.starts_with(|c: char| c.is_ascii_digit()) => {
+                (name.trim(), Some(version.to_string()))
+            }
+            _ => (v, None),
+        };
+        Some(ClientInfo { name: name.to_string(), version })
+    }
+
+    /// Best guess for a peer: the handshake `v` names the client more
+    /// precisely than the few characters of a peer id.
+    pub fn identify(peer_id: &[u8; 20], extension_handshake: Option<&Bencode>) -> Option<Self> {
+        extension_handshake.and_then(Self::from_extension_handshake).or_else(|| Self::from_peer_id(peer_id))
+    }
+}
+
+/// `4630` to `4.6.3`, trailing zero components dropped down to two.
+fn azureus_version(digits: &[u8]) -> Option<String> {
+    let mut components: Vec<u32> =
+        digits.iter().map(|&digit| (digit as char).to_digit(36)).collect::<Option<Vec<u32>>>()?;
+    while components.len() > 2 && components.last() == Some(&0) {
+        components.pop();
+    }
+    Some(components.iter().map(u32::to_string).collect::<Vec<String>>().join("."))
+}
+
+#[test]
+fn test_generate_peer_id() {
+    let peer_id = generate();
+    let prefix = client_prefix();
+    assert_eq!(prefix.len(), 8);
+    assert_eq!(&peer_id[..8], prefix.as_bytes());
+    assert!(peer_id[8..].iter().all(|byte| RANDOM_ALPHABET.contains(byte)));
+    assert_ne!(generate(), peer_id);
+    let version = env!("CARGO_PKG_VERSION");
+    let ours = ClientInfo::from_peer_id(&peer_id).unwrap();
+    assert_eq!(ours.name, CLIENT_NAME);
+    assert_eq!(ours.version, azureus_version(&prefix.as_bytes()[3..7]));
+    assert!(version.starts_with(ours.version.as_deref().unwrap()));
+    assert_eq!(client_version(), format!("{CLIENT_NAME} {version}"));
+}
+
+#[test]
+fn test_identify_clients() {
+    let id = |prefix: &[u8]| {
+        let mut peer_id = [b'x'; 20];
+        peer_id[..prefix.len()].copy_from_slice(prefix);
+        ClientInfo::from_peer_id(&peer_id).map(|client| client.to_string())
+    };
+    assert_eq!(id(b"-qB4630-").as_deref(), Some("qBittorrent 4.6.3"));
+    assert_eq!(id(b"-TR3000-").as_deref(), Some("Transmission 3.0"));
+    assert_eq!(id(b"-UT355W-").as_deref(), Some("µTorrent 3.5.5.32"));
+    assert_eq!(id(b"-lt0D80-").as_deref(), Some("rTorrent 0.13.8"));
+    assert_eq!(id(b"M7-10-3--").as_deref(), Some("Mainline 7.10.3"));
+    assert_eq!(id(b"-ZZ1000-"), None);
+    assert_eq!(id(b"M7-x-3--"), None);
+    assert_eq!(id(b"\x00\x01\x02"), None);
+
+    let mut handshake = Bencode::Dictionary(Vec::new());
+    handshake.insert("v", Bencode::from("qBittorrent/4.6.3")).unwrap();
+    let client = ClientInfo::identify(b"-qB4630-xxxxxxxxxxxx", Some(&handshake)).unwrap();
+    assert_eq!(client, ClientInfo { name: "qBittorrent".to_string(), version: Some("4.6.3".to_string()) });
+    handshake.insert("v", Bencode::from("Transmission 4.0.5")).unwrap();
+    assert_eq!(ClientInfo::from_extension_handshake(&handshake).unwrap().to_string(), "Transmission 4.0.5");
+    handshake.insert("v", Bencode::from("Tixati")).unwrap();
+    assert_eq!(ClientInfo::from_extension_handshake(&handshake).unwrap().version, None);
+    let without_v = Bencode::Dictionary(Vec::new());
+    assert_eq!(ClientInfo::identify(b"-TR4050-xxxxxxxxxxxx", Some(&without_v)).unwrap().name, "Transmission");
+}
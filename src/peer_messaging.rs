@@ -1,8 +1,27 @@
-use std::{error::Error, net::SocketAddr, mem};
+use std::{net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6}, time::{Duration, Instant}};
+#[cfg(test)]
+use std::mem;
 
 use derive_builder::Builder;
+use futures::future::join_all;
 use num_enum::TryFromPrimitive;
 use tokio::net::{lookup_host, UdpSocket};
+use tokio::time::timeout_at;
+
+/// Magic constant of every connect request.
+const PROTOCOL_ID: u64 = 0x41727101980;
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_SCRAPE: u32 = 2;
+const ACTION_ERROR: u32 = 3;
+/// A connection id may be used for one minute after it was received.
+const CONNECTION_ID_LIFETIME: Duration = Duration::from_secs(60);
+/// First timeout of the 15 * 2 ^ n retransmission schedule.
+const BASE_TIMEOUT: Duration = Duration::from_secs(15);
+/// n stops at 8, after that the tracker is considered dead.
+const MAX_RETRIES: u32 = 8;
+/// Info-hashes per scrape packet, more would not fit into a typical MTU.
+const MAX_SCRAPE_HASHES: usize = 74;
 
 #[derive(Debug)]
 enum AnnounceType {
@@ -12,40 +31,70 @@ enum AnnounceType {
 
 
 
-#[derive(Clone, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
 #[repr(u32)]
-enum AnnounceEventType {
+pub enum AnnounceEventType {
     UNDEFINED,
     COMPLETED,
-    STARTED, 
+    STARTED,
     STOPPED
 }
 
 //TODO: Maybe rewrite with tuple struct??
-//#[repr(packed)] //Maybe enable? GOOD: Easy memcopy BAD: may cause indian problems, may cause crush at ARM arch. 
+//#[repr(packed)] //Maybe enable? GOOD: Easy memcopy BAD: may cause indian problems, may cause crush at ARM arch.
+/// Announce parameters, `connection_id`, `action` and `transaction_id`
+/// are filled in by [`Announce::announce`].
 #[repr(C)]
-#[derive(Builder) ]
-struct IpV4AnnounceRequest {
+#[derive(Builder, Clone, Debug, PartialEq, Eq)]
+pub struct IpV4AnnounceRequest {
+    #[builder(default)]
     connection_id: u64,
+    #[builder(default = "ACTION_ANNOUNCE")]
     action: u32,
+    #[builder(default)]
     transaction_id: u32,
     info_hash: [u8; 20],
     peer_id: [u8; 20],
+    #[builder(default)]
     downloaded: u64,
     left: u64,
+    #[builder(default)]
     uploaded: u64,
+    #[builder(default = "AnnounceEventType::UNDEFINED")]
     event: AnnounceEventType,
+    /// 0 lets the tracker use the address the packet came from.
+    #[builder(default)]
     ip_address: u32,
+    #[builder(default)]
     key: u32,
+    /// -1 asks for the tracker's default number of peers.
+    #[builder(default = "u32::MAX")]
     num_want: u32,
     port: u16
 }
 impl IpV4AnnounceRequest {
+    const SIZE: usize = 98;
+
     fn to_bytes(&self) -> [u8; 98] {
-        return [0;98];
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..8].copy_from_slice(&self.connection_id.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.action.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.transaction_id.to_be_bytes());
+        bytes[16..36].copy_from_slice(&self.info_hash);
+        bytes[36..56].copy_from_slice(&self.peer_id);
+        bytes[56..64].copy_from_slice(&self.downloaded.to_be_bytes());
+        bytes[64..72].copy_from_slice(&self.left.to_be_bytes());
+        bytes[72..80].copy_from_slice(&self.uploaded.to_be_bytes());
+        bytes[80..84].copy_from_slice(&(self.event as u32).to_be_bytes());
+        bytes[84..88].copy_from_slice(&self.ip_address.to_be_bytes());
+        bytes[88..92].copy_from_slice(&self.key.to_be_bytes());
+        bytes[92..96].copy_from_slice(&self.num_want.to_be_bytes());
+        bytes[96..98].copy_from_slice(&self.port.to_be_bytes());
+        bytes
     }
+    #[cfg(test)]
     fn from_bytes(bytes: &[u8]) -> Option<Self> {
-        if bytes.len() <  104 {
+        if bytes.len() < Self::SIZE {
             return None;
         }
 
@@ -58,7 +107,7 @@ impl IpV4AnnounceRequest {
             downloaded: u64::from_be_bytes(bytes[56..64].try_into().unwrap()),
             left: u64::from_be_bytes(bytes[64..72].try_into().unwrap()),
             uploaded: u64::from_be_bytes(bytes[72..80].try_into().unwrap()),
-            event: AnnounceEventType::try_from(u32::from_be_bytes(bytes[80..84].try_into().unwrap())).unwrap(),
+            event: AnnounceEventType::try_from(u32::from_be_bytes(bytes[80..84].try_into().unwrap())).ok()?,
             ip_address: u32::from_be_bytes(bytes[84..88].try_into().unwrap()),
             key: u32::from_be_bytes(bytes[88..92].try_into().unwrap()),
             num_want: u32::from_be_bytes(bytes[92..96].try_into().unwrap()),
@@ -78,23 +127,14 @@ impl IpV4AnnounceResponse {
         let leechers = u32::from_be_bytes(bytes[12..16].try_into().unwrap());
         let seeders = u32::from_be_bytes(bytes[16..20].try_into().unwrap());
         let mut addresses = Vec::new();
-        if bytes.len() >= 26 {
-            let address_bytes = &bytes[20..];
-            if address_bytes.len() % 6 == 0 {
-                for i in (0..address_bytes.len()).step_by(6) {
-                    let ip = u32::from_be_bytes(//[
-                        address_bytes[i..i+4].try_into().unwrap()
-                        // address_bytes[i + 1],
-                        // address_bytes[i + 2],
-                        // address_bytes[i + 3],]
-                    );
-                    let port = u16::from_be_bytes([address_bytes[i + 4], address_bytes[i + 5]]);
-                    addresses.push(IpV4AnnounceAddress { ip, port });
-                }
-            }
-            else{
-                return None;
-            }
+        let address_bytes = &bytes[20..];
+        if !address_bytes.len().is_multiple_of(6) {
+            return None;
+        }
+        for i in (0..address_bytes.len()).step_by(6) {
+            let ip = u32::from_be_bytes(address_bytes[i..i + 4].try_into().unwrap());
+            let port = u16::from_be_bytes([address_bytes[i + 4], address_bytes[i + 5]]);
+            addresses.push(IpV4AnnounceAddress { ip, port });
         }
 
         Some(IpV4AnnounceResponse {
@@ -106,10 +146,18 @@ impl IpV4AnnounceResponse {
             addresses,
         })
     }
+
+    fn peers(&self) -> Vec<SocketAddr> {
+        self.addresses
+            .iter()
+            .map(|address| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::from(address.ip), address.port)))
+            .collect()
+    }
 }
 
 #[repr(C)]
-struct IpV4AnnounceResponse {
+#[derive(Debug)]
+pub struct IpV4AnnounceResponse {
     action: u32,
     transaction_id: u32,
     interval: u32,
@@ -119,140 +167,605 @@ struct IpV4AnnounceResponse {
 }
 
 #[repr(C)]
+#[derive(Debug)]
 struct IpV4AnnounceAddress {
     ip: u32,
     port: u16,
 }
 
+/// Announce response received over IPv6, same header as the IPv4 one but
+/// with 18 byte peers.
+#[repr(C)]
+#[derive(Debug)]
+pub struct IpV6AnnounceResponse {
+    action: u32,
+    transaction_id: u32,
+    interval: u32,
+    leechers: u32,
+    seeders: u32,
+    addresses: Vec<IpV6AnnounceAddress>,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct IpV6AnnounceAddress {
+    ip: u128,
+    port: u16,
+}
+
+impl IpV6AnnounceResponse {
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 20 {
+            return None;
+        }
+        let address_bytes = &bytes[20..];
+        if !address_bytes.len().is_multiple_of(18) {
+            return None;
+        }
+        let addresses = address_bytes
+            .chunks_exact(18)
+            .map(|address| IpV6AnnounceAddress {
+                ip: u128::from_be_bytes(address[0..16].try_into().unwrap()),
+                port: u16::from_be_bytes([address[16], address[17]]),
+            })
+            .collect();
+
+        Some(IpV6AnnounceResponse {
+            action: u32::from_be_bytes(bytes[0..4].try_into().unwrap()),
+            transaction_id: u32::from_be_bytes(bytes[4..8].try_into().unwrap()),
+            interval: u32::from_be_bytes(bytes[8..12].try_into().unwrap()),
+            leechers: u32::from_be_bytes(bytes[12..16].try_into().unwrap()),
+            seeders: u32::from_be_bytes(bytes[16..20].try_into().unwrap()),
+            addresses,
+        })
+    }
+
+    fn peers(&self) -> Vec<SocketAddr> {
+        self.addresses
+            .iter()
+            .map(|address| SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from(address.ip), address.port, 0, 0)))
+            .collect()
+    }
+}
+
+/// Announce response of either family, the peer format follows the
+/// family of the socket the request went out on.
+#[derive(Debug)]
+pub enum AnnounceResponse {
+    IPv4(IpV4AnnounceResponse),
+    IPv6(IpV6AnnounceResponse),
+}
+
+impl AnnounceResponse {
+    pub fn interval(&self) -> u32 {
+        match self {
+            AnnounceResponse::IPv4(response) => response.interval,
+            AnnounceResponse::IPv6(response) => response.interval,
+        }
+    }
+
+    pub fn leechers(&self) -> u32 {
+        match self {
+            AnnounceResponse::IPv4(response) => response.leechers,
+            AnnounceResponse::IPv6(response) => response.leechers,
+        }
+    }
+
+    pub fn seeders(&self) -> u32 {
+        match self {
+            AnnounceResponse::IPv4(response) => response.seeders,
+            AnnounceResponse::IPv6(response) => response.seeders,
+        }
+    }
+
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        match self {
+            AnnounceResponse::IPv4(response) => response.peers(),
+            AnnounceResponse::IPv6(response) => response.peers(),
+        }
+    }
+}
+
+/// Counters of one torrent from a scrape response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScrapeResult {
+    pub seeders: u32,
+    pub completed: u32,
+    pub leechers: u32,
+}
+
+impl ScrapeResult {
+    /// Results of a whole scrape response, 12 bytes per torrent after the
+    /// 8 byte header.
+    fn from_response(bytes: &[u8]) -> Option<Vec<Self>> {
+        let results = bytes.get(8..)?;
+        if !results.len().is_multiple_of(12) {
+            return None;
+        }
+        let value = |chunk: &[u8], at: usize| u32::from_be_bytes(chunk[at..at + 4].try_into().unwrap());
+        Some(
+            results
+                .chunks_exact(12)
+                .map(|chunk| ScrapeResult {
+                    seeders: value(chunk, 0),
+                    completed: value(chunk, 4),
+                    leechers: value(chunk, 8),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Why a single request/response exchange failed. Only timeouts are worth
+/// a retransmission.
+enum ExchangeError {
+    Timeout,
+    Failed(String),
+}
 
 #[derive(Debug)]
-struct Announce {
+pub struct Announce {
     host: String,
-    port: String,
-    ip: String,
     sock_addr: SocketAddr,
     sock: UdpSocket,
     connection_id: Option<u64>,
-    connected: bool,
+    /// When `connection_id` was received, it expires a minute later.
+    connection_id_received: Option<Instant>,
     announce_type: AnnounceType,
+    /// `15` seconds outside of tests.
+    base_timeout: Duration,
+    max_retries: u32,
 }
 
-async fn resolve_hostname_dns<T: AsRef<str>>(addr: T) -> Result<SocketAddr, String> {
-    let addr = addr.as_ref();
-    let resolved = lookup_host(addr).await;
-    match resolved {
-        Ok(mut addr) => match addr.next() {
-            Some(value) => Ok(value),
-            None => Err("Error during addr resolving".to_string()),
-        },
-        Err(err) => Err(err.to_string()),
+/// The first IPv4 and the first IPv6 address `addr` resolves to, a tracker
+/// with both A and AAAA records is announced to on both families.
+async fn resolve_hostname_all_families<T: AsRef<str>>(addr: T) -> Result<Vec<SocketAddr>, String> {
+    let resolved: Vec<SocketAddr> = lookup_host(addr.as_ref()).await.map_err(|err| err.to_string())?.collect();
+    let addresses: Vec<SocketAddr> = [
+        resolved.iter().find(|address| address.is_ipv4()),
+        resolved.iter().find(|address| address.is_ipv6()),
+    ]
+    .into_iter()
+    .flatten()
+    .copied()
+    .collect();
+    if addresses.is_empty() {
+        return Err("Error during addr resolving".to_string());
     }
+    Ok(addresses)
 }
 
+/// Announces to every tracker of `announcers`, usually the two address
+/// families of one host. They are asked at the same time so a dead path on
+/// one family does not hold up the other. Fails only when none of them
+/// answered.
+pub async fn announce_all(
+    announcers: &mut [Announce],
+    request: &IpV4AnnounceRequest,
+) -> Result<Vec<AnnounceResponse>, String> {
+    let answers = join_all(announcers.iter_mut().map(|announcer| async {
+        announcer.announce(request).await.map_err(|err| format!("{}: {err}", announcer.sock_addr))
+    }))
+    .await;
+    let (responses, errors): (Vec<_>, Vec<_>) = answers.into_iter().partition(Result::is_ok);
+    if responses.is_empty() {
+        return Err(errors.into_iter().filter_map(Result::err).collect::<Vec<_>>().join(", "));
+    }
+    Ok(responses.into_iter().filter_map(Result::ok).collect())
+}
 
 impl Announce {
-    async fn get_announce_data() {
-        
+    /// Announces with the 15 * 2 ^ n retransmission schedule and returns
+    /// the tracker's answer, `peers()` of it is the peer list. The request
+    /// layout is the same for both families, only the response differs.
+    async fn announce(&mut self, request: &IpV4AnnounceRequest) -> Result<AnnounceResponse, String> {
+        let response = self
+            .request(ACTION_ANNOUNCE, |connection_id, transaction_id| {
+                let mut request = request.clone();
+                request.connection_id = connection_id;
+                request.action = ACTION_ANNOUNCE;
+                request.transaction_id = transaction_id;
+                request.to_bytes().to_vec()
+            })
+            .await?;
+        let parsed = match self.announce_type {
+            AnnounceType::IPv4 => IpV4AnnounceResponse::from_bytes(&response).map(AnnounceResponse::IPv4),
+            AnnounceType::IPv6 => IpV6AnnounceResponse::from_bytes(&response).map(AnnounceResponse::IPv6),
+        };
+        parsed.ok_or_else(|| "Malformed announce response".to_string())
+    }
+
+    /// Seeders, completed and leechers of every torrent in `info_hashes`,
+    /// in the same order. Long lists are split into packets of
+    /// `MAX_SCRAPE_HASHES`.
+    pub async fn scrape(&mut self, info_hashes: &[[u8; 20]]) -> Result<Vec<ScrapeResult>, String> {
+        let mut results = Vec::with_capacity(info_hashes.len());
+        for chunk in info_hashes.chunks(MAX_SCRAPE_HASHES) {
+            let response = self
+                .request(ACTION_SCRAPE, |connection_id, transaction_id| {
+                    let mut request = Vec::with_capacity(16 + 20 * chunk.len());
+                    request.extend_from_slice(&connection_id.to_be_bytes());
+                    request.extend_from_slice(&ACTION_SCRAPE.to_be_bytes());
+                    request.extend_from_slice(&transaction_id.to_be_bytes());
+                    for info_hash in chunk {
+                        request.extend_from_slice(info_hash);
+                    }
+                    request
+                })
+                .await?;
+            let parsed = ScrapeResult::from_response(&response)
+                .filter(|parsed| parsed.len() == chunk.len())
+                .ok_or_else(|| "Malformed scrape response".to_string())?;
+            results.extend(parsed);
+        }
+        Ok(results)
     }
 
-    async fn get_connection_id(&mut self) -> Result<(), String> {
-        let mut buf: Vec<u8> = vec![0; 16];
-        buf[0..8].copy_from_slice(&(0x41727101980u64.to_be_bytes())); // Write magic constant. ALL IN BIG ENDIAN;
-        buf[8..12].copy_from_slice(&0u32.to_be_bytes());
-        buf[12..].copy_from_slice(&12345u32.to_be_bytes());
-        let _sended_len = self.sock.send(&mut buf).await.unwrap();
-        let mut buf = vec![0; 1024];
-        let recieved_len = self
-            .sock
-            .recv(&mut buf)
-            .await
-            .expect("Error while getting response");
-        buf.resize(recieved_len, 0);
-        println!("{recieved_len}");
-        assert!(buf.len() >= 16);
-        println!(
-            "Got Action (0=connect) {}",
-            u32::from_be_bytes(buf[0..4].try_into().unwrap())
-        );
-        println!(
-            "Got transaction_id sended 12345 {}",
-            u32::from_be_bytes(buf[4..8].try_into().unwrap())
-        );
-        println!(
-            "Got connection id {}",
-            u64::from_be_bytes(buf[8..16].try_into().unwrap())
-        );
-        self.connection_id = Some(u64::from_be_bytes(buf[8..16].try_into().unwrap()));
+    /// Sends the packet `build` makes from the connection and transaction
+    /// ids, on the 15 * 2 ^ n retransmission schedule. A new connection id
+    /// is fetched first whenever the current one has expired.
+    async fn request<F>(&mut self, action: u32, build: F) -> Result<Vec<u8>, String>
+    where
+        F: Fn(u64, u32) -> Vec<u8>,
+    {
+        for attempt in 0..=self.max_retries {
+            let timeout = self.base_timeout * 2u32.pow(attempt);
+            if !self.has_valid_connection_id() {
+                match self.connect(timeout).await {
+                    Ok(()) => {}
+                    Err(ExchangeError::Timeout) => continue,
+                    Err(ExchangeError::Failed(err)) => return Err(err),
+                }
+            }
+            let transaction_id = rand::random();
+            let request = build(self.connection_id.unwrap(), transaction_id);
+            match self.exchange(&request, transaction_id, action, timeout).await {
+                Ok(response) => return Ok(response),
+                Err(ExchangeError::Timeout) => continue,
+                Err(ExchangeError::Failed(err)) => return Err(err),
+            }
+        }
+        Err(format!("Tracker {} did not respond", self.host))
+    }
+
+    fn has_valid_connection_id(&self) -> bool {
+        match (self.connection_id, self.connection_id_received) {
+            (Some(_), Some(received)) => received.elapsed() < CONNECTION_ID_LIFETIME,
+            _ => false,
+        }
+    }
+
+    async fn connect(&mut self, timeout: Duration) -> Result<(), ExchangeError> {
+        let transaction_id = rand::random();
+        let mut buf = [0u8; 16];
+        buf[0..8].copy_from_slice(&PROTOCOL_ID.to_be_bytes()); // Write magic constant. ALL IN BIG ENDIAN;
+        buf[8..12].copy_from_slice(&ACTION_CONNECT.to_be_bytes());
+        buf[12..].copy_from_slice(&u32::to_be_bytes(transaction_id));
+        let response = self.exchange(&buf, transaction_id, ACTION_CONNECT, timeout).await?;
+        if response.len() < 16 {
+            return Err(ExchangeError::Failed("Connect response is too short".to_string()));
+        }
+        self.connection_id = Some(u64::from_be_bytes(response[8..16].try_into().unwrap()));
+        self.connection_id_received = Some(Instant::now());
         Ok(())
     }
 
-    async fn new<T: AsRef<str>>(addr: T) -> Result<Self, String> {
-        let created: Result<Announce, String> = {
-            let addr = addr.as_ref().to_string();
-            match addr.parse::<SocketAddr>().ok() {
-                Some(sock_addr) => match UdpSocket::bind("0.0.0.0:6881").await {
-                    Ok(sock) => Ok(Self {
-                        host: addr.split(':').next().unwrap().to_string(),
-                        port: sock_addr.port().to_string(),
-                        ip: sock_addr.ip().to_string(),
-                        sock_addr: sock_addr,
-                        sock: sock,
-                        connection_id: None,
-                        connected: false,
-                        announce_type: if sock_addr.is_ipv4() {
-                            AnnounceType::IPv4
-                        } else {
-                            AnnounceType::IPv6
-                        },
-                    }),
-                    Err(_err) => return Err("asdds".to_string()),
-                },
-                None => {
-                    // todo!();
-                    let resolved = resolve_hostname_dns(addr.clone()).await;
-                    match resolved {
-                        Ok(sock_addr) => match UdpSocket::bind("0.0.0.0:6881").await {
-                            Ok(sock) => Ok(Self {
-                                host: addr.split(':').next().unwrap().to_string(),
-                                port: sock_addr.port().to_string(),
-                                ip: sock_addr.ip().to_string(),
-                                sock_addr: sock_addr,
-                                sock: sock,
-                                connection_id: None,
-                                connected: false,
-                                announce_type: if sock_addr.is_ipv4() {
-                                    AnnounceType::IPv4
-                                } else {
-                                    AnnounceType::IPv6
-                                },
-                            }),
-                            Err(_err) => return Err("asdds".to_string()),
-                        },
-                        Err(err) => Err(err),
-                    }
+    /// Sends `request` once and waits up to `timeout` for the response with
+    /// the same transaction id. Stray packets, such as late answers to
+    /// earlier attempts, are skipped.
+    async fn exchange(
+        &self,
+        request: &[u8],
+        transaction_id: u32,
+        action: u32,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, ExchangeError> {
+        self.sock.send(request).await.map_err(|err| ExchangeError::Failed(err.to_string()))?;
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut buf = vec![0; 2048];
+        loop {
+            let received = match timeout_at(deadline, self.sock.recv(&mut buf)).await {
+                Ok(Ok(received)) => received,
+                Ok(Err(err)) => return Err(ExchangeError::Failed(err.to_string())),
+                Err(_) => return Err(ExchangeError::Timeout),
+            };
+            if received < 8 || u32::from_be_bytes(buf[4..8].try_into().unwrap()) != transaction_id {
+                continue;
+            }
+            match u32::from_be_bytes(buf[0..4].try_into().unwrap()) {
+                ACTION_ERROR => {
+                    let message = String::from_utf8_lossy(&buf[8..received]);
+                    return Err(ExchangeError::Failed(format!("Tracker error: {message}")));
+                }
+                received_action if received_action == action => return Ok(buf[..received].to_vec()),
+                received_action => {
+                    return Err(ExchangeError::Failed(format!(
+                        "Tracker answered with action {received_action}, expected {action}"
+                    )))
                 }
             }
+        }
+    }
+
+    /// One announcer per address family `addr` resolves to.
+    pub async fn new_all_families<T: AsRef<str>>(addr: T) -> Result<Vec<Self>, String> {
+        let addr = addr.as_ref();
+        let sock_addrs = match addr.parse::<SocketAddr>() {
+            Ok(sock_addr) => vec![sock_addr],
+            Err(_) => resolve_hostname_all_families(addr).await?,
         };
-        match created {
-            Ok(mut created) => match created.sock.connect(created.sock_addr).await {
-                Ok(_) => {
-                    created.connected = true;
-                    Ok(created)
-                }
-                Err(err) => Err(err.to_string()),
-            },
-            Err(err) => Err(err),
+        let mut announcers = Vec::with_capacity(sock_addrs.len());
+        for sock_addr in sock_addrs {
+            announcers.push(Self::with_address(addr, sock_addr).await?);
+        }
+        Ok(announcers)
+    }
+
+    /// Binds a socket of the same family as `sock_addr`.
+    async fn with_address(addr: &str, sock_addr: SocketAddr) -> Result<Self, String> {
+        let (bind_addr, announce_type) = if sock_addr.is_ipv4() {
+            ("0.0.0.0:0", AnnounceType::IPv4)
+        } else {
+            ("[::]:0", AnnounceType::IPv6)
+        };
+        let sock = UdpSocket::bind(bind_addr).await.map_err(|err| err.to_string())?;
+        sock.connect(sock_addr).await.map_err(|err| err.to_string())?;
+        Ok(Self {
+            host: addr.rsplit_once(':').map_or(addr, |(host, _)| host).to_string(),
+            sock_addr,
+            sock,
+            connection_id: None,
+            connection_id_received: None,
+            announce_type,
+            base_timeout: BASE_TIMEOUT,
+            max_retries: MAX_RETRIES,
+        })
+    }
+}
+
+/// Minimal tracker on localhost for the tests. `handler` gets every packet
+/// and returns the answer, `None` drops the packet.
+#[cfg(test)]
+async fn spawn_fake_tracker<F>(handler: F) -> SocketAddr
+where
+    F: FnMut(&[u8]) -> Option<Vec<u8>> + Send + 'static,
+{
+    spawn_fake_tracker_on("127.0.0.1:0", handler).await
+}
+
+#[cfg(test)]
+async fn spawn_fake_tracker_on<F>(bind_addr: &str, mut handler: F) -> SocketAddr
+where
+    F: FnMut(&[u8]) -> Option<Vec<u8>> + Send + 'static,
+{
+    let sock = UdpSocket::bind(bind_addr).await.unwrap();
+    let addr = sock.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = vec![0; 2048];
+        while let Ok((len, from)) = sock.recv_from(&mut buf).await {
+            if let Some(response) = handler(&buf[..len]) {
+                sock.send_to(&response, from).await.unwrap();
+            }
+        }
+    });
+    addr
+}
+
+#[cfg(test)]
+fn fake_connect_response(request: &[u8], connection_id: u64) -> Vec<u8> {
+    assert_eq!(&request[0..8], &PROTOCOL_ID.to_be_bytes());
+    let mut response = vec![0; 8];
+    response[4..8].copy_from_slice(&request[12..16]);
+    response.extend_from_slice(&connection_id.to_be_bytes());
+    response
+}
+
+#[cfg(test)]
+fn test_request() -> IpV4AnnounceRequest {
+    IpV4AnnounceRequestBuilder::default()
+        .info_hash([7; 20])
+        .peer_id(*b"-CT0100-abcdefghijkl")
+        .left(1000)
+        .event(AnnounceEventType::STARTED)
+        .port(6881)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_announce_to_fake_tracker() {
+    let mut connects = 0;
+    let tracker = spawn_fake_tracker(move |packet| {
+        if packet.len() == 16 {
+            connects += 1;
+            // Drop the first connect to force a retransmission.
+            return (connects > 1).then(|| fake_connect_response(packet, 0xabcdef));
+        }
+        let request = IpV4AnnounceRequest::from_bytes(packet).unwrap();
+        assert_eq!(request.connection_id, 0xabcdef);
+        assert_eq!(request.event, AnnounceEventType::STARTED);
+        assert_eq!(request.num_want, u32::MAX);
+        assert_eq!(request.port, 6881);
+        let mut response = Vec::new();
+        for value in [ACTION_ANNOUNCE, request.transaction_id, 1800, 3, 5] {
+            response.extend_from_slice(&value.to_be_bytes());
+        }
+        response.extend_from_slice(&[10, 0, 0, 1, 0x1a, 0xe1, 192, 168, 1, 2, 0, 80]);
+        Some(response)
+    })
+    .await;
+
+    let mut announcer = Announce::with_address("tracker", tracker).await.unwrap();
+    announcer.base_timeout = Duration::from_millis(50);
+    let response = announcer.announce(&test_request()).await.unwrap();
+    assert_eq!((response.interval(), response.leechers(), response.seeders()), (1800, 3, 5));
+    assert_eq!(
+        response.peers(),
+        vec!["10.0.0.1:6881".parse::<SocketAddr>().unwrap(), "192.168.1.2:80".parse().unwrap()]
+    );
+
+    // An expired connection id is replaced before the next announce.
+    announcer.connection_id_received = Some(Instant::now() - CONNECTION_ID_LIFETIME);
+    assert!(!announcer.has_valid_connection_id());
+    announcer.announce(&test_request()).await.unwrap();
+    assert!(announcer.has_valid_connection_id());
+}
+
+#[tokio::test]
+async fn test_announce_errors() {
+    let tracker = spawn_fake_tracker(|packet| {
+        if packet.len() == 16 {
+            return Some(fake_connect_response(packet, 1));
         }
-        //return created;
+        let mut response = ACTION_ERROR.to_be_bytes().to_vec();
+        response.extend_from_slice(&packet[12..16]);
+        response.extend_from_slice(b"torrent not registered");
+        Some(response)
+    })
+    .await;
+    let mut announcer = Announce::with_address("tracker", tracker).await.unwrap();
+    announcer.base_timeout = Duration::from_millis(50);
+    let err = announcer.announce(&test_request()).await.unwrap_err();
+    assert_eq!(err, "Tracker error: torrent not registered");
+
+    let silent = spawn_fake_tracker(|_| None).await;
+    let mut announcer = Announce::with_address("tracker", silent).await.unwrap();
+    announcer.base_timeout = Duration::from_millis(10);
+    announcer.max_retries = 2;
+    let started = Instant::now();
+    assert!(announcer.announce(&test_request()).await.is_err());
+    // 10 + 20 + 40 ms for the connect attempts
+    assert!(started.elapsed() >= Duration::from_millis(70));
+}
+
+#[tokio::test]
+async fn test_scrape() {
+    let packet_sizes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let sizes = packet_sizes.clone();
+    let tracker = spawn_fake_tracker(move |packet| {
+        if packet.len() == 16 {
+            return Some(fake_connect_response(packet, 9));
+        }
+        assert_eq!(&packet[0..8], &9u64.to_be_bytes());
+        assert_eq!(&packet[8..12], &ACTION_SCRAPE.to_be_bytes());
+        let hashes = &packet[16..];
+        sizes.lock().unwrap().push(hashes.len() / 20);
+        let mut response = ACTION_SCRAPE.to_be_bytes().to_vec();
+        response.extend_from_slice(&packet[12..16]);
+        for hash in hashes.chunks(20) {
+            for value in [hash[0] as u32, 100, 2 * hash[0] as u32] {
+                response.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        Some(response)
+    })
+    .await;
+
+    let mut announcer = Announce::with_address("tracker", tracker).await.unwrap();
+    announcer.base_timeout = Duration::from_millis(50);
+    let info_hashes: Vec<[u8; 20]> = (0..80u8).map(|i| [i; 20]).collect();
+    let results = announcer.scrape(&info_hashes).await.unwrap();
+    assert_eq!(*packet_sizes.lock().unwrap(), vec![74, 6]);
+    assert_eq!(results.len(), 80);
+    assert_eq!(results[77], ScrapeResult { seeders: 77, completed: 100, leechers: 154 });
+
+    assert_eq!(ScrapeResult::from_response(&[0; 8]), Some(Vec::new()));
+    assert!(ScrapeResult::from_response(&[0; 14]).is_none());
+}
+
+#[tokio::test]
+async fn test_announce_ipv6() {
+    let handler = |peer: &'static [u8]| {
+        move |packet: &[u8]| {
+            if packet.len() == 16 {
+                return Some(fake_connect_response(packet, 2));
+            }
+            let mut response = ACTION_ANNOUNCE.to_be_bytes().to_vec();
+            response.extend_from_slice(&packet[12..16]);
+            for value in [900u32, 0, 1] {
+                response.extend_from_slice(&value.to_be_bytes());
+            }
+            response.extend_from_slice(peer);
+            Some(response)
+        }
+    };
+    let v6_peer: &[u8] = &[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x1a, 0xe1];
+    let tracker_v6 = spawn_fake_tracker_on("[::1]:0", handler(v6_peer)).await;
+    let tracker_v4 = spawn_fake_tracker(handler(&[10, 0, 0, 1, 0x1a, 0xe1])).await;
+
+    let mut announcers = vec![
+        Announce::with_address("tracker", tracker_v4).await.unwrap(),
+        Announce::new_all_families(tracker_v6.to_string()).await.unwrap().remove(0),
+    ];
+    assert!(matches!(announcers[1].announce_type, AnnounceType::IPv6));
+    assert!(announcers[1].sock.local_addr().unwrap().is_ipv6());
+    for announcer in &mut announcers {
+        announcer.base_timeout = Duration::from_millis(50);
     }
+    let responses = announce_all(&mut announcers, &test_request()).await.unwrap();
+    let peers: Vec<SocketAddr> = responses.iter().flat_map(AnnounceResponse::peers).collect();
+    assert_eq!(peers, vec!["10.0.0.1:6881".parse::<SocketAddr>().unwrap(), "[2001:db8::1]:6881".parse().unwrap()]);
+    assert_eq!(responses[1].interval(), 900);
+
+    assert!(IpV6AnnounceResponse::from_bytes(&[0; 26]).is_none());
+}
+
+#[tokio::test]
+async fn test_announce_all_concurrently() {
+    let mut connects = 0;
+    let answering = spawn_fake_tracker(move |packet| {
+        if packet.len() == 16 {
+            connects += 1;
+            // The first connect is lost, this family also waits a full timeout.
+            return (connects > 1).then(|| fake_connect_response(packet, 3));
+        }
+        let mut response = ACTION_ANNOUNCE.to_be_bytes().to_vec();
+        response.extend_from_slice(&packet[12..16]);
+        for value in [900u32, 0, 1] {
+            response.extend_from_slice(&value.to_be_bytes());
+        }
+        response.extend_from_slice(&[10, 0, 0, 1, 0x1a, 0xe1]);
+        Some(response)
+    })
+    .await;
+    let silent = spawn_fake_tracker(|_| None).await;
+    let mut announcers = vec![
+        Announce::with_address("tracker", silent).await.unwrap(),
+        Announce::with_address("tracker", answering).await.unwrap(),
+    ];
+    for announcer in &mut announcers {
+        announcer.base_timeout = Duration::from_millis(300);
+        announcer.max_retries = 1;
+    }
+    let started = Instant::now();
+    let responses = announce_all(&mut announcers, &test_request()).await.unwrap();
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].peers(), vec!["10.0.0.1:6881".parse::<SocketAddr>().unwrap()]);
+    // 300 + 600 ms for the silent one, one after the other would add 300 ms.
+    assert!(started.elapsed() < Duration::from_millis(1100), "{:?}", started.elapsed());
+
+    let err = announce_all(&mut announcers[..1], &test_request()).await.unwrap_err();
+    assert!(err.starts_with(&silent.to_string()), "{err}");
+}
+
+#[test]
+fn test_announce_request_round_trip() {
+    let mut request = test_request();
+    request.connection_id = 42;
+    request.transaction_id = 7;
+    let bytes = request.to_bytes();
+    assert_eq!(&bytes[8..12], &ACTION_ANNOUNCE.to_be_bytes());
+    assert_eq!(&bytes[80..84], &2u32.to_be_bytes());
+    assert_eq!(IpV4AnnounceRequest::from_bytes(&bytes), Some(request));
+    assert!(IpV4AnnounceResponse::from_bytes(&[0; 25]).is_none());
 }
 
 #[tokio::test]
 async fn test_announce() {
-    let mut announcer = Announce::new("opentor.net:6969").await.unwrap();
-    announcer.get_connection_id().await;
+    let mut announcers = Announce::new_all_families("opentor.net:6969").await.unwrap();
+    let responses = announce_all(&mut announcers, &test_request()).await;
 
-    print!("{:?}", announcer);
+    print!("{:?}", responses);
 }
 
 #[tokio::test]
@@ -277,7 +790,7 @@ async fn test_connect_to_udp_peer() {
     buf[0..8].copy_from_slice(&(0x41727101980u64.to_be_bytes())); // Write magic constant. ALL IN BIG ENDIAN;
     buf[8..12].copy_from_slice(&0u32.to_be_bytes());
     buf[12..].copy_from_slice(&12345u32.to_be_bytes());
-    let _sended_len = sock.send(&mut buf).await.unwrap();
+    let _sended_len = sock.send(&buf).await.unwrap();
     let mut buf = vec![0; 1024];
     let recieved_len = sock
         .recv(&mut buf)
@@ -0,0 +1,439 @@
+use std::io;
+
+use bencoding::{decode_bencode_with_options, Bencode, DecodeOptions, InfoHash};
+use bytes::{Buf, BufMut, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::metadata::{MetadataMessage, EXTENSION_NAME};
+use crate::peer_id::client_version;
+
+/// Length prefixed protocol name every handshake starts with.
+pub const PROTOCOL: &[u8; 20] = b"\x13BitTorrent protocol";
+pub const HANDSHAKE_LEN: usize = 68;
+/// Requests for more are refused, and so are pieces carrying more. Clients
+/// ask for 16 KiB blocks and a few old ones for up to 128 KiB.
+pub const MAX_REQUEST_LENGTH: u32 = 128 * 1024;
+/// Default frame limit. Fits a piece message of the largest request and
+/// the bitfield of a torrent with 8 million pieces.
+pub const DEFAULT_MAX_MESSAGE_LENGTH: usize = 1024 * 1024;
+/// Extended message id of the extension handshake (BEP 10).
+pub const EXTENDED_HANDSHAKE_ID: u8 = 0;
+/// Id peers use for the `ut_metadata` messages they send us, announced in
+/// our extension handshake.
+pub const UT_METADATA_ID: u8 = 1;
+
+/// Reserved bit of the extension protocol, `reserved[5] & 0x10`.
+const EXTENSION_BIT: (usize, u8) = (5, 0x10);
+
+/// The 68 bytes each side sends before any message (BEP 3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake {
+    pub reserved: [u8; 8],
+    pub info_hash: InfoHash,
+    pub peer_id: [u8; 20],
+}
+
+impl Handshake {
+    /// Our handshake, advertising the extension protocol.
+    pub fn new(info_hash: InfoHash, peer_id: [u8; 20]) -> Self {
+        let mut reserved = [0; 8];
+        reserved[EXTENSION_BIT.0] |= EXTENSION_BIT.1;
+        Handshake { reserved, info_hash, peer_id }
+    }
+
+    pub fn supports_extensions(&self) -> bool {
+        self.reserved[EXTENSION_BIT.0] & EXTENSION_BIT.1 != 0
+    }
+
+    pub fn to_bytes(self) -> [u8; HANDSHAKE_LEN] {
+        let mut bytes = [0; HANDSHAKE_LEN];
+        bytes[0..20].copy_from_slice(PROTOCOL);
+        bytes[20..28].copy_from_slice(&self.reserved);
+        bytes[28..48].copy_from_slice(self.info_hash.as_bytes());
+        bytes[48..68].copy_from_slice(&self.peer_id);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != HANDSHAKE_LEN {
+            return Err(format!("handshake of {} bytes", bytes.len()));
+        }
+        if &bytes[0..20] != PROTOCOL {
+            return Err("peer does not speak the BitTorrent protocol".to_string());
+        }
+        Ok(Handshake {
+            reserved: bytes[20..28].try_into().unwrap(),
+            info_hash: InfoHash(bytes[28..48].try_into().unwrap()),
+            peer_id: bytes[48..68].try_into().unwrap(),
+        })
+    }
+}
+
+/// Sends `ours` and reads the peer's handshake, which has to be for the
+/// same torrent.
+pub async fn handshake<S>(stream: &mut S, ours: &Handshake) -> Result<Handshake, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(&ours.to_bytes()).await.map_err(|err| err.to_string())?;
+    let mut bytes = [0; HANDSHAKE_LEN];
+    stream.read_exact(&mut bytes).await.map_err(|err| err.to_string())?;
+    let theirs = Handshake::from_bytes(&bytes)?;
+    if theirs.info_hash != ours.info_hash {
+        return Err(format!("peer answered for torrent {}", theirs.info_hash));
+    }
+    Ok(theirs)
+}
+
+/// Peer wire message, the payload of one length-prefixed frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    Have { piece: u32 },
+    Bitfield(Vec<u8>),
+    Request { index: u32, begin: u32, length: u32 },
+    Piece { index: u32, begin: u32, data: Vec<u8> },
+    Cancel { index: u32, begin: u32, length: u32 },
+    /// DHT port (BEP 5).
+    Port(u16),
+    /// Extension protocol message (BEP 10), `id` 0 is the handshake.
+    Extended { id: u8, payload: Vec<u8> },
+}
+
+impl Message {
+    fn id(&self) -> Option<u8> {
+        Some(match self {
+            Message::KeepAlive => return None,
+            Message::Choke => 0,
+            Message::Unchoke => 1,
+            Message::Interested => 2,
+            Message::NotInterested => 3,
+            Message::Have { .. } => 4,
+            Message::Bitfield(_) => 5,
+            Message::Request { .. } => 6,
+            Message::Piece { .. } => 7,
+            Message::Cancel { .. } => 8,
+            Message::Port(_) => 9,
+            Message::Extended { .. } => 20,
+        })
+    }
+
+    /// The frame, length prefix included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend(self.id());
+        match self {
+            Message::Have { piece } => payload.extend_from_slice(&piece.to_be_bytes()),
+            Message::Bitfield(bits) => payload.extend_from_slice(bits),
+            Message::Request { index, begin, length } | Message::Cancel { index, begin, length } => {
+                for value in [index, begin, length] {
+                    payload.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            Message::Piece { index, begin, data } => {
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&begin.to_be_bytes());
+                payload.extend_from_slice(data);
+            }
+            Message::Port(port) => payload.extend_from_slice(&port.to_be_bytes()),
+            Message::Extended { id, payload: extended } => {
+                payload.push(*id);
+                payload.extend_from_slice(extended);
+            }
+            _ => {}
+        }
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    /// Parses a frame without its length prefix. Messages with a fixed size
+    /// must have exactly that size.
+    pub fn from_payload(payload: &[u8]) -> Result<Self, String> {
+        let Some((&id, body)) = payload.split_first() else {
+            return Ok(Message::KeepAlive);
+        };
+        let expect = |length: usize| match body.len() == length {
+            true => Ok(()),
+            false => Err(format!("message {id} with {} payload bytes, expected {length}", body.len())),
+        };
+        let int = |at: usize| u32::from_be_bytes(body[at..at + 4].try_into().unwrap());
+        let message = match id {
+            0..=3 => {
+                expect(0)?;
+                [Message::Choke, Message::Unchoke, Message::Interested, Message::NotInterested][id as usize].clone()
+            }
+            4 => {
+                expect(4)?;
+                Message::Have { piece: int(0) }
+            }
+            5 => Message::Bitfield(body.to_vec()),
+            6 | 8 => {
+                expect(12)?;
+                let (index, begin, length) = (int(0), int(4), int(8));
+                if length == 0 || length > MAX_REQUEST_LENGTH {
+                    return Err(format!("request of {length} bytes"));
+                }
+                match id {
+                    6 => Message::Request { index, begin, length },
+                    _ => Message::Cancel { index, begin, length },
+                }
+            }
+            7 => {
+                if body.len() < 8 {
+                    return Err("piece message without index and offset".to_string());
+                }
+                if body.len() - 8 > MAX_REQUEST_LENGTH as usize {
+                    return Err(format!("piece of {} bytes", body.len() - 8));
+                }
+                Message::Piece { index: int(0), begin: int(4), data: body[8..].to_vec() }
+            }
+            9 => {
+                expect(2)?;
+                Message::Port(u16::from_be_bytes([body[0], body[1]]))
+            }
+            20 => {
+                let (&extended_id, extended) = body.split_first().ok_or("extended message without id")?;
+                Message::Extended { id: extended_id, payload: extended.to_vec() }
+            }
+            _ => return Err(format!("unknown message id {id}")),
+        };
+        Ok(message)
+    }
+
+    /// `ut_metadata` message to a peer, `peer_id` is the id from the peer's
+    /// extension handshake.
+    pub fn metadata(peer_id: u8, message: &MetadataMessage) -> Self {
+        Message::Extended { id: peer_id, payload: message.to_bytes() }
+    }
+}
+
+/// Our extension handshake, `metadata_size` is known once we have the info
+/// dictionary.
+pub fn extension_handshake(metadata_size: Option<usize>) -> Message {
+    let mut extensions = Bencode::Dictionary(Vec::new());
+    extensions.insert(EXTENSION_NAME, Bencode::Integer(UT_METADATA_ID.into())).unwrap();
+    let mut handshake = Bencode::Dictionary(Vec::new());
+    handshake.insert("m", extensions).unwrap();
+    if let Some(size) = metadata_size {
+        handshake.insert("metadata_size", Bencode::Integer(size as i64)).unwrap();
+    }
+    handshake.insert("v", Bencode::from(client_version())).unwrap();
+    Message::Extended { id: EXTENDED_HANDSHAKE_ID, payload: handshake.to_bencode_bytes().unwrap() }
+}
+
+/// Incoming extended message, by the ids we announced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtendedMessage {
+    Handshake(Bencode),
+    Metadata(MetadataMessage),
+    /// An id we never announced.
+    Unknown(u8),
+}
+
+impl ExtendedMessage {
+    pub fn parse(id: u8, payload: &[u8]) -> Result<Self, String> {
+        match id {
+            EXTENDED_HANDSHAKE_ID => {
+                let handshake = decode_bencode_with_options(payload, &DecodeOptions::lenient())
+                    .map_err(|err| format!("invalid extension handshake: {err}"))?;
+                match handshake {
+                    Bencode::Dictionary(_) => Ok(ExtendedMessage::Handshake(handshake)),
+                    _ => Err("extension handshake is not a dictionary".to_string()),
+                }
+            }
+            UT_METADATA_ID => MetadataMessage::from_bytes(payload).map(ExtendedMessage::Metadata),
+            id => Ok(ExtendedMessage::Unknown(id)),
+        }
+    }
+}
+
+/// Checks a peer's bitfield: one bit per piece and the spare bits of the
+/// last byte cleared.
+pub fn check_bitfield(bitfield: &[u8], piece_count: usize) -> Result<(), String> {
+    if bitfield.len() != piece_count.div_ceil(8) {
+        return Err(format!("bitfield of {} bytes for {piece_count} pieces", bitfield.len()));
+    }
+    let spare_bits = bitfield.len() * 8 - piece_count;
+    if spare_bits > 0 && bitfield[bitfield.len() - 1] & ((1 << spare_bits) - 1) != 0 {
+        return Err("bitfield has spare bits set".to_string());
+    }
+    Ok(())
+}
+
+/// Length-prefixed framing of [`Message`]s, for use after the handshake.
+/// Frames longer than the limit are an error before anything is buffered.
+#[derive(Debug, Clone)]
+pub struct MessageCodec {
+    max_length: usize,
+}
+
+impl MessageCodec {
+    pub fn new() -> Self {
+        Self::with_max_length(DEFAULT_MAX_MESSAGE_LENGTH)
+    }
+
+    pub fn with_max_length(max_length: usize) -> Self {
+        MessageCodec { max_length }
+    }
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, io::Error> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes(src[0..4].try_into().unwrap()) as usize;
+        if length > self.max_length {
+            return Err(invalid_data(format!("message of {length} bytes exceeds {}", self.max_length)));
+        }
+        if src.len() < 4 + length {
+            src.reserve(4 + length - src.len());
+            return Ok(None);
+        }
+        src.advance(4);
+        let payload = src.split_to(length);
+        Message::from_payload(&payload).map(Some).map_err(invalid_data)
+    }
+}
+
+impl Encoder<&Message> for MessageCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: &Message, dst: &mut BytesMut) -> Result<(), io::Error> {
+        dst.put_slice(&item.to_bytes());
+        Ok(())
+    }
+}
+
+/// Hand-written session start in the framing a peer sends, not a capture:
+/// a handshake with a qBittorrent style peer id, a minimal extension
+/// handshake, a bitfield of 10 pieces, unchoke, have, a 4 byte piece, a
+/// keep-alive and a DHT port.
+#[cfg(test)]
+const SYNTHETIC_SESSION: &[u8] = b"\
+\x13BitTorrent protocol\x00\x00\x00\x00\x00\x10\x00\x05\
+\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\
+-qB4630-a1b2c3d4e5f6\
+\x00\x00\x00\x46\x14\x00d1:md11:ut_metadatai3ee13:metadata_sizei31235e1:v15:qBittorrent/4.6e\
+\x00\x00\x00\x03\x05\xff\xc0\
+\x00\x00\x00\x01\x01\
+\x00\x00\x00\x05\x04\x00\x00\x00\x03\
+\x00\x00\x00\x0d\x07\x00\x00\x00\x00\x00\x00\x40\x00abcd\
+\x00\x00\x00\x00\
+\x00\x00\x00\x03\x09\x1a\xe1";
+
+#[test]
+fn test_decode_synthetic_session() {
+    let (handshake_bytes, frames) = SYNTHETIC_SESSION.split_at(HANDSHAKE_LEN);
+    let handshake = Handshake::from_bytes(handshake_bytes).unwrap();
+    assert!(handshake.supports_extensions());
+    assert_eq!(handshake.info_hash, InfoHash([3; 20]));
+    assert_eq!(&handshake.peer_id, b"-qB4630-a1b2c3d4e5f6");
+    assert_eq!(handshake.to_bytes().as_slice(), handshake_bytes);
+
+    // Fed in uneven chunks the way reads split them.
+    let mut codec = MessageCodec::new();
+    let mut buffer = BytesMut::new();
+    let mut messages = Vec::new();
+    for chunk in frames.chunks(7) {
+        buffer.extend_from_slice(chunk);
+        while let Some(message) = codec.decode(&mut buffer).unwrap() {
+            messages.push(message);
+        }
+    }
+    assert!(buffer.is_empty());
+    assert_eq!(messages.len(), 7);
+    assert_eq!(messages[1], Message::Bitfield(vec![0xff, 0xc0]));
+    check_bitfield(&[0xff, 0xc0], 10).unwrap();
+    assert_eq!(messages[2..], [
+        Message::Unchoke,
+        Message::Have { piece: 3 },
+        Message::Piece { index: 0, begin: 0x4000, data: b"abcd".to_vec() },
+        Message::KeepAlive,
+        Message::Port(6881),
+    ]);
+    let Message::Extended { id, payload } = &messages[0] else { panic!("{:?}", messages[0]) };
+    let ExtendedMessage::Handshake(extensions) = ExtendedMessage::parse(*id, payload).unwrap() else { panic!() };
+    assert_eq!(crate::metadata::from_extension_handshake(&extensions), Some((3, Some(31235))));
+
+    let encoded: Vec<u8> = messages.iter().flat_map(Message::to_bytes).collect();
+    assert_eq!(encoded, frames);
+}
+
+#[test]
+fn test_message_limits() {
+    let decode = |bytes: &[u8]| MessageCodec::with_max_length(64).decode(&mut BytesMut::from(bytes));
+    // Rejected from the length prefix alone.
+    assert!(decode(&[0, 0, 0, 65]).is_err());
+    assert!(decode(&[0, 0, 0, 64, 5]).unwrap().is_none());
+    assert!(decode(&[0, 0, 0, 2, 1, 0]).is_err());
+    assert!(decode(&[0, 0, 0, 4, 4, 0, 0, 0]).is_err());
+    assert!(decode(&[0, 0, 0, 1, 10]).is_err());
+    assert!(decode(&[0, 0, 0, 1, 20]).is_err());
+    let request = |length: u32| Message::Request { index: 1, begin: 0, length }.to_bytes();
+    assert!(decode(&request(16384)).unwrap().is_some());
+    assert!(decode(&request(MAX_REQUEST_LENGTH + 1)).is_err());
+    assert!(decode(&request(0)).is_err());
+    let piece = |length: usize| [&[7, 0, 0, 0, 1, 0, 0, 0, 0][..], &vec![0; length]].concat();
+    assert!(Message::from_payload(&piece(MAX_REQUEST_LENGTH as usize)).is_ok());
+    assert_eq!(Message::from_payload(&piece(1 << 20)), Err("piece of 1048576 bytes".to_string()));
+
+    assert!(check_bitfield(&[0xff, 0xe0], 10).is_err());
+    assert!(check_bitfield(&[0xff], 10).is_err());
+    assert!(Handshake::from_bytes(&[0; HANDSHAKE_LEN]).is_err());
+}
+
+#[tokio::test]
+async fn test_handshake_and_metadata() {
+    let (mut ours, mut theirs) = tokio::io::duplex(256);
+    let info_hash = InfoHash([3; 20]);
+    let peer = tokio::spawn(async move {
+        let mut bytes = [0; HANDSHAKE_LEN];
+        theirs.read_exact(&mut bytes).await.unwrap();
+        let received = Handshake::from_bytes(&bytes).unwrap();
+        theirs.write_all(&SYNTHETIC_SESSION[..HANDSHAKE_LEN]).await.unwrap();
+        received
+    });
+    let handshake_sent = Handshake::new(info_hash, *b"-CT0100-abcdefghijkl");
+    let received = handshake(&mut ours, &handshake_sent).await.unwrap();
+    assert_eq!(&received.peer_id[..8], b"-qB4630-");
+    assert_eq!(peer.await.unwrap(), handshake_sent);
+
+    let (mut ours, mut theirs) = tokio::io::duplex(256);
+    tokio::spawn(async move {
+        let mut bytes = [0; HANDSHAKE_LEN];
+        theirs.read_exact(&mut bytes).await.unwrap();
+        theirs.write_all(&Handshake::new(InfoHash([4; 20]), [0; 20]).to_bytes()).await.unwrap();
+    });
+    assert!(handshake(&mut ours, &handshake_sent).await.is_err());
+
+    let Message::Extended { id, payload } = extension_handshake(Some(31235)) else { panic!() };
+    let ExtendedMessage::Handshake(extensions) = ExtendedMessage::parse(id, &payload).unwrap() else { panic!() };
+    assert_eq!(crate::metadata::from_extension_handshake(&extensions), Some((UT_METADATA_ID, Some(31235))));
+    let client = crate::peer_id::ClientInfo::from_extension_handshake(&extensions).unwrap();
+    assert_eq!(client.name, crate::peer_id::CLIENT_NAME);
+
+    let request = MetadataMessage::Request { piece: 1 };
+    let Message::Extended { id, payload } = Message::metadata(UT_METADATA_ID, &request) else { panic!() };
+    assert_eq!(ExtendedMessage::parse(id, &payload).unwrap(), ExtendedMessage::Metadata(request));
+    assert_eq!(ExtendedMessage::parse(7, b"").unwrap(), ExtendedMessage::Unknown(7));
+}
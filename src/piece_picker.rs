@@ -0,0 +1,518 @@
+use std::net::SocketAddr;
+
+use rand::seq::SliceRandom;
+
+use crate::peer_wire::check_bitfield;
+
+/// Size of a request, the last block of a piece may be shorter.
+pub const BLOCK_SIZE: u32 = 16 * 1024;
+/// Until this many pieces are complete pieces are picked at random instead
+/// of rarest first, a complete piece is worth more than a rare one then.
+pub const RANDOM_FIRST_PIECES: usize = 4;
+
+/// Pieces of one peer, or our own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitfield {
+    bits: Vec<u8>,
+    len: usize,
+}
+
+impl Bitfield {
+    pub fn new(len: usize) -> Self {
+        Bitfield { bits: vec![0; len.div_ceil(8)], len }
+    }
+
+    /// Bitfield message payload of a torrent with `len` pieces.
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Result<Self, String> {
+        check_bitfield(bytes, len)?;
+        Ok(Bitfield { bits: bytes.to_vec(), len })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn has(&self, piece: usize) -> bool {
+        piece < self.len && self.bits[piece / 8] & (0x80 >> (piece % 8)) != 0
+    }
+
+    pub fn set(&mut self, piece: usize) {
+        if piece < self.len {
+            self.bits[piece / 8] |= 0x80 >> (piece % 8);
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.bits.iter().map(|byte| byte.count_ones() as usize).sum()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.count() == self.len
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+}
+
+/// Part of a piece requested with one request message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Block {
+    pub piece: u32,
+    pub begin: u32,
+    pub length: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Not downloaded at all.
+    Skip,
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PickMode {
+    #[default]
+    RarestFirst,
+    /// Lowest index first, for playing files while they download.
+    Sequential,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BlockState {
+    Missing,
+    /// Peers the block is requested from, more than one in endgame.
+    Requested(Vec<SocketAddr>),
+    Received,
+}
+
+#[derive(Debug, Clone)]
+struct PieceState {
+    /// Connected peers that have the piece.
+    availability: u32,
+    have: bool,
+    priority: Priority,
+    /// Empty until the first block is requested.
+    blocks: Vec<BlockState>,
+}
+
+/// Result of [`PiecePicker::block_received`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockReceived {
+    /// Other peers the block was requested from, they get a cancel.
+    pub cancels: Vec<SocketAddr>,
+    /// Every block of the piece is here, it can be verified.
+    pub piece_complete: bool,
+}
+
+/// Decides which blocks to request from which peer. Partly requested
+/// pieces are finished first, then new pieces are started by priority and
+/// the pick mode. Once every missing block is requested the picker enters
+/// endgame and hands out blocks already requested from other peers.
+#[derive(Debug, Clone)]
+pub struct PiecePicker {
+    piece_length: u64,
+    total_length: u64,
+    pieces: Vec<PieceState>,
+    mode: PickMode,
+    random_first_pieces: usize,
+    have_count: usize,
+}
+
+impl PiecePicker {
+    pub fn new(piece_length: u64, total_length: u64) -> Self {
+        let piece_count = total_length.div_ceil(piece_length) as usize;
+        let piece = PieceState { availability: 0, have: false, priority: Priority::Normal, blocks: Vec::new() };
+        PiecePicker {
+            piece_length,
+            total_length,
+            pieces: vec![piece; piece_count],
+            mode: PickMode::default(),
+            random_first_pieces: RANDOM_FIRST_PIECES,
+            have_count: 0,
+        }
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.pieces.len()
+    }
+
+    pub fn piece_size(&self, piece: u32) -> u32 {
+        let start = piece as u64 * self.piece_length;
+        (self.total_length - start).min(self.piece_length) as u32
+    }
+
+    fn block_count(&self, piece: u32) -> usize {
+        self.piece_size(piece).div_ceil(BLOCK_SIZE) as usize
+    }
+
+    fn block(&self, piece: u32, index: usize) -> Block {
+        let begin = index as u32 * BLOCK_SIZE;
+        Block { piece, begin, length: (self.piece_size(piece) - begin).min(BLOCK_SIZE) }
+    }
+
+    pub fn set_mode(&mut self, mode: PickMode) {
+        self.mode = mode;
+    }
+
+    /// Priorities of the files of the torrent, in order with their lengths.
+    /// A piece gets the highest priority of the files it overlaps, files
+    /// past the end of the torrent are ignored.
+    pub fn set_file_priorities(&mut self, files: &[(u64, Priority)]) {
+        for piece in &mut self.pieces {
+            piece.priority = Priority::Skip;
+        }
+        let Some(last_piece) = self.pieces.len().checked_sub(1) else {
+            return;
+        };
+        let mut offset = 0;
+        for &(length, priority) in files.iter().filter(|(length, _)| *length > 0) {
+            let first = (offset / self.piece_length) as usize;
+            if first > last_piece {
+                break;
+            }
+            let last = (((offset + length - 1) / self.piece_length) as usize).min(last_piece);
+            for piece in &mut self.pieces[first..=last] {
+                piece.priority = piece.priority.max(priority);
+            }
+            offset += length;
+        }
+    }
+
+    pub fn peer_bitfield(&mut self, pieces: &Bitfield) {
+        for (index, piece) in self.pieces.iter_mut().enumerate() {
+            piece.availability += pieces.has(index) as u32;
+        }
+    }
+
+    pub fn peer_have(&mut self, piece: u32) {
+        if let Some(piece) = self.pieces.get_mut(piece as usize) {
+            piece.availability += 1;
+        }
+    }
+
+    /// Forgets a disconnected peer: its pieces and its requests.
+    pub fn peer_disconnected(&mut self, peer: SocketAddr, pieces: &Bitfield) {
+        for (index, piece) in self.pieces.iter_mut().enumerate() {
+            if pieces.has(index) {
+                piece.availability = piece.availability.saturating_sub(1);
+            }
+            for block in &mut piece.blocks {
+                release(block, peer);
+            }
+        }
+    }
+
+    /// Puts a timed out, rejected or cancelled request back.
+    pub fn cancel_request(&mut self, peer: SocketAddr, block: Block) {
+        let index = (block.begin / BLOCK_SIZE) as usize;
+        if let Some(block) = self.pieces.get_mut(block.piece as usize).and_then(|piece| piece.blocks.get_mut(index)) {
+            release(block, peer);
+        }
+    }
+
+    pub fn block_received(&mut self, peer: SocketAddr, block: Block) -> BlockReceived {
+        let index = (block.begin / BLOCK_SIZE) as usize;
+        let Some(piece) = self.pieces.get_mut(block.piece as usize) else {
+            return BlockReceived::default();
+        };
+        let Some(state) = piece.blocks.get_mut(index) else {
+            return BlockReceived::default();
+        };
+        let cancels = match std::mem::replace(state, BlockState::Received) {
+            BlockState::Requested(peers) => peers.into_iter().filter(|other| *other != peer).collect(),
+            BlockState::Received => return BlockReceived::default(),
+            _ => Vec::new(),
+        };
+        let piece_complete = piece.blocks.iter().all(|block| *block == BlockState::Received);
+        BlockReceived { cancels, piece_complete }
+    }
+
+    /// The piece passed its hash check.
+    pub fn mark_have(&mut self, piece: u32) {
+        if let Some(state) = self.pieces.get_mut(piece as usize) {
+            if !state.have {
+                state.have = true;
+                state.blocks = Vec::new();
+                self.have_count += 1;
+            }
+        }
+    }
+
+    /// The piece failed its hash check, all of it is downloaded again.
+    pub fn piece_failed(&mut self, piece: u32) {
+        if let Some(state) = self.pieces.get_mut(piece as usize) {
+            state.blocks = Vec::new();
+        }
+    }
+
+    /// Received blocks of pieces that are not finished, for resume data.
+    pub fn unfinished(&self) -> Vec<(u32, Bitfield)> {
+        self.pieces
+            .iter()
+            .enumerate()
+            .filter(|(_, piece)| !piece.have && piece.blocks.contains(&BlockState::Received))
+            .map(|(index, piece)| {
+                let mut received = Bitfield::new(piece.blocks.len());
+                for (block, state) in piece.blocks.iter().enumerate() {
+                    if *state == BlockState::Received {
+                        received.set(block);
+                    }
+                }
+                (index as u32, received)
+            })
+            .collect()
+    }
+
+    /// Blocks received before a restart, ignored for pieces we have and
+    /// when the block count does not match.
+    pub fn restore_blocks(&mut self, piece: u32, received: &Bitfield) {
+        if piece as usize >= self.pieces.len() || received.len() != self.block_count(piece) {
+            return;
+        }
+        let state = &mut self.pieces[piece as usize];
+        if !state.have {
+            state.blocks = (0..received.len())
+                .map(|block| match received.has(block) {
+                    true => BlockState::Received,
+                    false => BlockState::Missing,
+                })
+                .collect();
+        }
+    }
+
+    pub fn have(&self, piece: u32) -> bool {
+        self.pieces.get(piece as usize).is_some_and(|piece| piece.have)
+    }
+
+    /// Every piece that is not skipped is here.
+    pub fn is_complete(&self) -> bool {
+        self.pieces.iter().all(|piece| piece.have || piece.priority == Priority::Skip)
+    }
+
+    /// No block is left that has not been requested from someone.
+    pub fn in_endgame(&self) -> bool {
+        self.pieces
+            .iter()
+            .enumerate()
+            .filter(|(_, piece)| !piece.have && piece.priority != Priority::Skip)
+            .all(|(index, piece)| {
+                piece.blocks.len() == self.block_count(index as u32)
+                    && !piece.blocks.contains(&BlockState::Missing)
+            })
+            && !self.is_complete()
+    }
+
+    /// Up to `count` blocks to request from `peer`, marked as requested.
+    pub fn pick(&mut self, peer: SocketAddr, peer_pieces: &Bitfield, count: usize) -> Vec<Block> {
+        let candidates = self.candidates(peer_pieces);
+        let mut picked = Vec::new();
+        for &piece in &candidates {
+            if picked.len() == count {
+                return picked;
+            }
+            if self.pieces[piece as usize].blocks.is_empty() {
+                self.pieces[piece as usize].blocks = vec![BlockState::Missing; self.block_count(piece)];
+            }
+            for index in 0..self.pieces[piece as usize].blocks.len() {
+                if picked.len() == count {
+                    break;
+                }
+                let state = &mut self.pieces[piece as usize].blocks[index];
+                if *state == BlockState::Missing {
+                    *state = BlockState::Requested(vec![peer]);
+                    picked.push(self.block(piece, index));
+                }
+            }
+        }
+        if picked.len() < count && self.in_endgame() {
+            for &piece in &candidates {
+                for index in 0..self.pieces[piece as usize].blocks.len() {
+                    if picked.len() == count {
+                        return picked;
+                    }
+                    if let BlockState::Requested(peers) = &mut self.pieces[piece as usize].blocks[index] {
+                        if !peers.contains(&peer) {
+                            peers.push(peer);
+                            picked.push(self.block(piece, index));
+                        }
+                    }
+                }
+            }
+        }
+        picked
+    }
+
+    /// Pieces worth requesting from a peer with `peer_pieces`, best first.
+    fn candidates(&self, peer_pieces: &Bitfield) -> Vec<u32> {
+        let mut candidates: Vec<u32> = (0..self.pieces.len() as u32)
+            .filter(|&index| {
+                let piece = &self.pieces[index as usize];
+                !piece.have && piece.priority != Priority::Skip && peer_pieces.has(index as usize)
+            })
+            .collect();
+        // Shuffled first so that the stable sort breaks ties at random,
+        // peers should not all start on the same piece.
+        candidates.shuffle(&mut rand::thread_rng());
+        let random_first = self.have_count < self.random_first_pieces;
+        candidates.sort_by_key(|&index| {
+            let piece = &self.pieces[index as usize];
+            let partial = piece.blocks.iter().any(|block| *block != BlockState::Missing);
+            let order = match self.mode {
+                PickMode::Sequential => index,
+                PickMode::RarestFirst if random_first => 0,
+                PickMode::RarestFirst => piece.availability,
+            };
+            (!partial, std::cmp::Reverse(piece.priority), order)
+        });
+        candidates
+    }
+}
+
+fn release(block: &mut BlockState, peer: SocketAddr) {
+    if let BlockState::Requested(peers) = block {
+        peers.retain(|other| *other != peer);
+        if peers.is_empty() {
+            *block = BlockState::Missing;
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_peer(port: u16) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, 1], port))
+}
+
+#[cfg(test)]
+fn full_bitfield(len: usize) -> Bitfield {
+    let mut bitfield = Bitfield::new(len);
+    (0..len).for_each(|piece| bitfield.set(piece));
+    bitfield
+}
+
+#[test]
+fn test_rarest_first_and_sequential() {
+    // 6 pieces of 2 blocks, the last one a single short block.
+    let mut picker = PiecePicker::new(2 * BLOCK_SIZE as u64, 5 * BLOCK_SIZE as u64 * 2 + 100);
+    assert_eq!(picker.piece_count(), 6);
+    assert_eq!(picker.piece_size(5), 100);
+    picker.random_first_pieces = 0;
+    let everything = full_bitfield(6);
+    picker.peer_bitfield(&everything);
+    let mut rare = Bitfield::new(6);
+    rare.set(1);
+    rare.set(4);
+    rare.set(5);
+    for _ in 0..2 {
+        picker.peer_bitfield(&rare);
+    }
+    picker.peer_have(4);
+    // 1, 4 and 5 are the most common, the rest are tied.
+    let picked = picker.pick(test_peer(1), &everything, 4);
+    assert_eq!(picked.len(), 4);
+    assert!(picked.iter().all(|block| ![1, 4, 5].contains(&block.piece)));
+    assert_eq!(picked[0].piece, picked[1].piece);
+    assert_eq!((picked[1].begin, picked[1].length), (BLOCK_SIZE, BLOCK_SIZE));
+
+    // A started piece is finished before a new one is started.
+    picker.cancel_request(test_peer(1), picked[3]);
+    let next = picker.pick(test_peer(2), &everything, 1);
+    assert_eq!(next, vec![picked[3]]);
+
+    let mut sequential = PiecePicker::new(2 * BLOCK_SIZE as u64, 5 * BLOCK_SIZE as u64 * 2 + 100);
+    sequential.set_mode(PickMode::Sequential);
+    let blocks = sequential.pick(test_peer(1), &everything, 12);
+    let pieces: Vec<u32> = blocks.iter().map(|block| block.piece).collect();
+    assert_eq!(pieces, vec![0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5]);
+    assert_eq!(blocks[10], Block { piece: 5, begin: 0, length: 100 });
+}
+
+#[test]
+fn test_file_priorities() {
+    let piece_length = BLOCK_SIZE as u64;
+    let mut picker = PiecePicker::new(piece_length, 4 * piece_length);
+    // Piece 1 is shared by the skipped and the high priority file.
+    picker.set_file_priorities(&[
+        (piece_length + 10, Priority::Skip),
+        (0, Priority::High),
+        (piece_length, Priority::High),
+        (2 * piece_length - 10, Priority::Low),
+    ]);
+    let everything = full_bitfield(4);
+    let mut pieces: Vec<u32> = picker.pick(test_peer(1), &everything, 10).iter().map(|block| block.piece).collect();
+    assert_eq!(pieces.len(), 3);
+    assert_eq!(pieces[2], 3);
+    pieces.sort();
+    assert_eq!(pieces, [1, 2, 3]);
+
+    for piece in 1..4 {
+        picker.mark_have(piece);
+    }
+    assert!(picker.is_complete());
+    assert!(picker.pick(test_peer(1), &everything, 10).is_empty());
+
+    // Lengths beyond the torrent, or no pieces at all.
+    picker.set_file_priorities(&[
+        (piece_length, Priority::High),
+        (10 * piece_length, Priority::Skip),
+        (piece_length, Priority::High),
+    ]);
+    assert!(!picker.is_complete());
+    PiecePicker::new(piece_length, 0).set_file_priorities(&[(piece_length, Priority::High)]);
+}
+
+#[test]
+fn test_endgame() {
+    let mut picker = PiecePicker::new(2 * BLOCK_SIZE as u64, 3 * BLOCK_SIZE as u64);
+    picker.set_mode(PickMode::Sequential);
+    let (a, b) = (test_peer(1), test_peer(2));
+    let everything = full_bitfield(2);
+    picker.peer_bitfield(&everything);
+    picker.peer_bitfield(&everything);
+    let from_a = picker.pick(a, &everything, 10);
+    assert_eq!(from_a.len(), 3);
+    assert!(picker.in_endgame());
+
+    // b gets the same blocks, every block at most once per peer.
+    assert_eq!(picker.pick(b, &everything, 10), from_a);
+    assert!(picker.pick(b, &everything, 10).is_empty());
+
+    let received = picker.block_received(b, from_a[0]);
+    assert_eq!(received, BlockReceived { cancels: vec![a], piece_complete: false });
+    let received = picker.block_received(a, from_a[1]);
+    assert_eq!(received, BlockReceived { cancels: vec![b], piece_complete: true });
+    // The same block arriving twice completes the piece only once.
+    assert_eq!(picker.block_received(b, from_a[1]), BlockReceived::default());
+    picker.mark_have(from_a[0].piece);
+
+    // A failed piece leaves endgame until it is requested again.
+    picker.piece_failed(from_a[2].piece);
+    assert!(!picker.in_endgame());
+    assert_eq!(picker.pick(b, &everything, 10), vec![from_a[2]]);
+    picker.peer_disconnected(b, &everything);
+    assert_eq!(picker.pick(a, &everything, 10), vec![from_a[2]]);
+    picker.block_received(a, from_a[2]);
+    picker.mark_have(from_a[2].piece);
+    assert!(picker.is_complete() && !picker.in_endgame());
+}
+
+#[test]
+fn test_bitfield() {
+    let mut bitfield = Bitfield::new(10);
+    bitfield.set(0);
+    bitfield.set(9);
+    bitfield.set(10);
+    assert_eq!(bitfield.as_bytes(), &[0x80, 0x40]);
+    assert!(bitfield.has(9) && !bitfield.has(10));
+    assert_eq!(bitfield.count(), 2);
+    assert_eq!(Bitfield::from_bytes(&[0x80, 0x40], 10).unwrap(), bitfield);
+    assert!(Bitfield::from_bytes(&[0x80, 0x60], 10).is_err());
+    assert!(full_bitfield(10).is_complete());
+}
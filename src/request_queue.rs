@@ -0,0 +1,261 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use bencoding::Bencode;
+
+use crate::peer_wire::Message;
+use crate::piece_picker::{Bitfield, Block, PiecePicker, BLOCK_SIZE};
+
+/// Requests outstanding on a new connection, grown by one per received
+/// block until the first throughput sample.
+const INITIAL_DEPTH: usize = 4;
+const MIN_DEPTH: usize = 2;
+const MAX_DEPTH: usize = 500;
+/// Assumed `reqq` of peers that do not send one.
+pub const DEFAULT_PEER_REQQ: usize = 250;
+/// Data kept in flight on top of one round trip, covers jitter and the
+/// time a request waits in the peer's queue.
+const QUEUE_SLACK: Duration = Duration::from_secs(1);
+/// Throughput is sampled over windows of this length.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+const MIN_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+/// A peer that sent nothing for this long while we waited is snubbing us.
+const SNUB_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+struct Outstanding {
+    block: Block,
+    sent_at: Instant,
+}
+
+/// Requests to one peer. Keeps about a round trip plus a second of data in
+/// flight, measured from the peer's throughput and the fastest round trip
+/// seen, never more than the peer's `reqq`.
+#[derive(Debug, Clone)]
+pub struct RequestQueue {
+    outstanding: Vec<Outstanding>,
+    depth: usize,
+    peer_reqq: usize,
+    /// Smoothed time from request to block, queueing included.
+    srtt: Option<Duration>,
+    /// Fastest round trip, the latency without queueing.
+    min_rtt: Option<Duration>,
+    /// Bytes per second, `None` until the first full window.
+    throughput: Option<f64>,
+    window_start: Instant,
+    window_bytes: u64,
+    /// Last block received, or when we started waiting for one.
+    last_progress: Instant,
+    snubbed: bool,
+}
+
+impl RequestQueue {
+    pub fn new(now: Instant) -> Self {
+        RequestQueue {
+            outstanding: Vec::new(),
+            depth: INITIAL_DEPTH,
+            peer_reqq: DEFAULT_PEER_REQQ,
+            srtt: None,
+            min_rtt: None,
+            throughput: None,
+            window_start: now,
+            window_bytes: 0,
+            last_progress: now,
+            snubbed: false,
+        }
+    }
+
+    /// Takes `reqq` from the peer's extension handshake.
+    pub fn set_extension_handshake(&mut self, handshake: &Bencode) {
+        let reqq = handshake.get("reqq").and_then(Bencode::as_int).and_then(|reqq| usize::try_from(reqq).ok());
+        if let Some(reqq) = reqq {
+            self.peer_reqq = reqq.max(1);
+        }
+    }
+
+    /// How many requests should be outstanding now.
+    pub fn depth(&self) -> usize {
+        match self.snubbed {
+            true => 1,
+            false => self.depth.min(self.peer_reqq),
+        }
+    }
+
+    pub fn is_snubbed(&self) -> bool {
+        self.snubbed
+    }
+
+    pub fn outstanding(&self) -> impl Iterator<Item = Block> + '_ {
+        self.outstanding.iter().map(|outstanding| outstanding.block)
+    }
+
+    /// Requests from `picker` to top the queue up to [`Self::depth`].
+    pub fn fill(
+        &mut self,
+        picker: &mut PiecePicker,
+        peer: SocketAddr,
+        peer_pieces: &Bitfield,
+        now: Instant,
+    ) -> Vec<Message> {
+        let wanted = self.depth().saturating_sub(self.outstanding.len());
+        if wanted == 0 {
+            return Vec::new();
+        }
+        if self.outstanding.is_empty() {
+            self.last_progress = now;
+        }
+        let blocks = picker.pick(peer, peer_pieces, wanted);
+        self.outstanding.extend(blocks.iter().map(|&block| Outstanding { block, sent_at: now }));
+        blocks
+            .into_iter()
+            .map(|Block { piece, begin, length }| Message::Request { index: piece, begin, length })
+            .collect()
+    }
+
+    /// Records a received block, `false` when it was never requested or
+    /// already timed out.
+    pub fn on_block(&mut self, block: Block, now: Instant) -> bool {
+        let Some(position) = self.outstanding.iter().position(|outstanding| outstanding.block == block) else {
+            return false;
+        };
+        let outstanding = self.outstanding.remove(position);
+        let rtt = now.saturating_duration_since(outstanding.sent_at);
+        self.srtt = Some(match self.srtt {
+            Some(srtt) => (srtt * 7 + rtt) / 8,
+            None => rtt,
+        });
+        self.min_rtt = Some(self.min_rtt.map_or(rtt, |min_rtt| min_rtt.min(rtt)));
+        self.last_progress = now;
+        self.snubbed = false;
+
+        self.window_bytes += block.length as u64;
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed >= THROUGHPUT_WINDOW {
+            let rate = self.window_bytes as f64 / elapsed.as_secs_f64();
+            self.throughput = Some(match self.throughput {
+                Some(throughput) => throughput * 0.7 + rate * 0.3,
+                None => rate,
+            });
+            self.window_start = now;
+            self.window_bytes = 0;
+        }
+        self.depth = match self.throughput {
+            // Slow start, doubles the depth every round trip.
+            None => (self.depth + 1).min(MAX_DEPTH),
+            Some(throughput) => {
+                let in_flight = throughput * (self.min_rtt.unwrap_or_default() + QUEUE_SLACK).as_secs_f64();
+                ((in_flight / BLOCK_SIZE as f64).ceil() as usize).clamp(MIN_DEPTH, MAX_DEPTH)
+            }
+        };
+        true
+    }
+
+    /// Three smoothed round trips, within sane bounds.
+    pub fn request_timeout(&self) -> Duration {
+        self.srtt.map_or(DEFAULT_REQUEST_TIMEOUT, |srtt| (srtt * 3).clamp(MIN_REQUEST_TIMEOUT, MAX_REQUEST_TIMEOUT))
+    }
+
+    /// Removes and returns requests that waited too long, for the caller to
+    /// cancel and hand back to the picker. A peer that sent nothing for
+    /// [`SNUB_TIMEOUT`] is snubbed: all its requests are returned and it is
+    /// kept to one request until a block arrives.
+    pub fn timed_out(&mut self, now: Instant) -> Vec<Block> {
+        if !self.outstanding.is_empty() && now.saturating_duration_since(self.last_progress) >= SNUB_TIMEOUT {
+            self.snubbed = true;
+            self.depth = INITIAL_DEPTH;
+            return self.clear();
+        }
+        let timeout = self.request_timeout();
+        let (expired, waiting) = self
+            .outstanding
+            .iter()
+            .partition(|outstanding| now.saturating_duration_since(outstanding.sent_at) >= timeout);
+        self.outstanding = waiting;
+        expired.into_iter().map(|outstanding: Outstanding| outstanding.block).collect()
+    }
+
+    /// Drops every outstanding request, after a choke or a disconnect. The
+    /// peer discards the requests itself when it chokes us.
+    pub fn clear(&mut self) -> Vec<Block> {
+        self.outstanding.drain(..).map(|outstanding| outstanding.block).collect()
+    }
+}
+
+#[cfg(test)]
+fn test_setup(pieces: usize) -> (PiecePicker, Bitfield, SocketAddr) {
+    let mut picker = PiecePicker::new(4 * BLOCK_SIZE as u64, pieces as u64 * 4 * BLOCK_SIZE as u64);
+    picker.set_mode(crate::piece_picker::PickMode::Sequential);
+    let mut bitfield = Bitfield::new(pieces);
+    (0..pieces).for_each(|piece| bitfield.set(piece));
+    (picker, bitfield, SocketAddr::from(([10, 0, 0, 1], 6881)))
+}
+
+#[test]
+fn test_adaptive_depth() {
+    let (mut picker, pieces, peer) = test_setup(100);
+    let start = Instant::now();
+    let mut queue = RequestQueue::new(start);
+    let requests = queue.fill(&mut picker, peer, &pieces, start);
+    assert_eq!(requests.len(), INITIAL_DEPTH);
+    assert_eq!(requests[1], Message::Request { index: 0, begin: BLOCK_SIZE, length: BLOCK_SIZE });
+    assert!(queue.fill(&mut picker, peer, &pieces, start).is_empty());
+
+    // A peer sending 40 blocks a second with 100 ms latency.
+    let mut now = start;
+    for _ in 0..80 {
+        now += Duration::from_millis(25);
+        let block = queue.outstanding().next().unwrap();
+        assert!(queue.on_block(block, now + Duration::from_millis(75)));
+        queue.fill(&mut picker, peer, &pieces, now);
+    }
+    assert!(!queue.on_block(Block { piece: 99, begin: 0, length: BLOCK_SIZE }, now));
+    // 40 blocks/s over 100 ms plus a second of slack.
+    let depth = queue.depth();
+    assert!((40..=50).contains(&depth), "depth {depth}");
+    assert_eq!(queue.outstanding().count(), depth);
+
+    let mut handshake = Bencode::Dictionary(Vec::new());
+    handshake.insert("reqq", Bencode::Integer(16)).unwrap();
+    queue.set_extension_handshake(&handshake);
+    assert_eq!(queue.depth(), 16);
+    // Already over the limit, nothing new is requested.
+    assert!(queue.fill(&mut picker, peer, &pieces, now).is_empty());
+}
+
+#[test]
+fn test_timeouts_and_snubs() {
+    let (mut picker, pieces, peer) = test_setup(10);
+    let start = Instant::now();
+    let mut queue = RequestQueue::new(start);
+    queue.fill(&mut picker, peer, &pieces, start);
+    let first = queue.outstanding().next().unwrap();
+    let received = start + Duration::from_millis(500);
+    queue.on_block(first, received);
+    assert_eq!(queue.request_timeout(), MIN_REQUEST_TIMEOUT);
+    let later = start + Duration::from_secs(3);
+    assert_eq!(queue.fill(&mut picker, peer, &pieces, later).len(), 2);
+
+    // The three from the start expire, the two sent later wait.
+    let expired = queue.timed_out(start + MIN_REQUEST_TIMEOUT);
+    assert_eq!(expired.len(), 3);
+    assert_eq!(queue.outstanding().count(), 2);
+    for block in expired {
+        picker.cancel_request(peer, block);
+    }
+    assert!(!queue.is_snubbed());
+
+    let snub_time = received + SNUB_TIMEOUT;
+    assert_eq!(queue.timed_out(snub_time).len(), 2);
+    assert!(queue.is_snubbed());
+    assert_eq!(queue.depth(), 1);
+    let requests = queue.fill(&mut picker, peer, &pieces, snub_time);
+    assert_eq!(requests, vec![Message::Request { index: 0, begin: BLOCK_SIZE, length: BLOCK_SIZE }]);
+    queue.on_block(Block { piece: 0, begin: BLOCK_SIZE, length: BLOCK_SIZE }, snub_time);
+    assert!(!queue.is_snubbed());
+
+    queue.fill(&mut picker, peer, &pieces, snub_time);
+    assert_eq!(queue.clear().len(), queue.depth());
+    assert_eq!(queue.outstanding().count(), 0);
+}
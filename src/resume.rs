@@ -0,0 +1,304 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bencoding::{decode_bencode_with_options, Bencode, DecodeOptions, InfoHash, Metainfo};
+
+use crate::network_manager::compact_peers;
+use crate::piece_picker::{Bitfield, PiecePicker, BLOCK_SIZE};
+use crate::storage::{FileState, Storage};
+
+const FILE_FORMAT: &str = "ConsoleTorrent resume file";
+const FILE_VERSION: i64 = 1;
+
+/// Transfer totals kept across restarts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub uploaded: u64,
+    pub downloaded: u64,
+    /// Time the torrent was running, whole seconds are saved.
+    pub active_time: Duration,
+}
+
+/// State of a torrent saved next to its download so a restart does not
+/// hash every file again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumeData {
+    pub info_hash: InfoHash,
+    pub pieces: Bitfield,
+    /// Files when the data was saved, the pieces only hold if they match.
+    pub files: Vec<FileState>,
+    /// Received blocks of pieces that are not complete yet.
+    pub unfinished: Vec<(u32, Bitfield)>,
+    pub peers: Vec<SocketAddr>,
+    /// Tracker urls by tier.
+    pub trackers: Vec<Vec<String>>,
+    pub stats: Stats,
+}
+
+impl ResumeData {
+    /// Pieces and blocks of `picker` with the files of `storage`, which is
+    /// flushed first so the modification times are final. Peers, trackers
+    /// and stats are left for the caller to fill in.
+    pub fn capture(info_hash: InfoHash, storage: &mut Storage, picker: &PiecePicker) -> Result<Self, String> {
+        storage.flush()?;
+        let mut pieces = Bitfield::new(picker.piece_count());
+        for piece in 0..picker.piece_count() {
+            if picker.have(piece as u32) {
+                pieces.set(piece);
+            }
+        }
+        Ok(ResumeData {
+            info_hash,
+            pieces,
+            files: storage.file_states(),
+            unfinished: picker.unfinished(),
+            peers: Vec::new(),
+            trackers: Vec::new(),
+            stats: Stats::default(),
+        })
+    }
+
+    /// `<info hash in hex>.resume` in `dir`.
+    pub fn path(dir: impl AsRef<Path>, info_hash: &InfoHash) -> PathBuf {
+        dir.as_ref().join(format!("{}.resume", info_hash.to_hex()))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let files = self
+            .files
+            .iter()
+            .map(|file| {
+                let mut entry = Bencode::Dictionary(Vec::new());
+                entry.insert("size", Bencode::from(file.length as i64))?;
+                entry.insert("mtime", Bencode::from(file.mtime))?;
+                Ok(entry)
+            })
+            .collect::<Result<Vec<Bencode>, bencoding::PathError>>()
+            .map_err(|e| e.to_string())?;
+        let unfinished = self
+            .unfinished
+            .iter()
+            .map(|(piece, blocks)| {
+                let mut entry = Bencode::Dictionary(Vec::new());
+                entry.insert("piece", Bencode::from(*piece as i64))?;
+                entry.insert("blocks", Bencode::from(blocks.as_bytes().to_vec()))?;
+                Ok(entry)
+            })
+            .collect::<Result<Vec<Bencode>, bencoding::PathError>>()
+            .map_err(|e| e.to_string())?;
+        let trackers = self
+            .trackers
+            .iter()
+            .map(|tier| Bencode::from(tier.iter().map(|url| Bencode::from(url.as_str())).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        let (peers, peers6) = encode_peers(&self.peers);
+
+        let mut resume = Bencode::Dictionary(Vec::new());
+        let fields = [
+            ("file-format", Bencode::from(FILE_FORMAT)),
+            ("file-version", Bencode::from(FILE_VERSION)),
+            ("info-hash", Bencode::from(self.info_hash.as_bytes().to_vec())),
+            ("pieces", Bencode::from(self.pieces.as_bytes().to_vec())),
+            ("files", Bencode::from(files)),
+            ("unfinished", Bencode::from(unfinished)),
+            ("peers", Bencode::from(peers)),
+            ("peers6", Bencode::from(peers6)),
+            ("trackers", Bencode::from(trackers)),
+            ("uploaded", Bencode::from(self.stats.uploaded as i64)),
+            ("downloaded", Bencode::from(self.stats.downloaded as i64)),
+            ("active-time", Bencode::from(self.stats.active_time.as_secs() as i64)),
+        ];
+        for (key, value) in fields {
+            resume.insert(key, value).map_err(|e| e.to_string())?;
+        }
+        resume.to_bencode_bytes().map_err(|e| e.to_string())
+    }
+
+    /// Resume data of `metainfo`, checked against its info hash and piece
+    /// count.
+    pub fn from_bytes(data: &[u8], metainfo: &Metainfo) -> Result<Self, String> {
+        let resume = decode_bencode_with_options(data, &DecodeOptions::lenient()).map_err(|e| e.to_string())?;
+        if resume.get("file-format").and_then(Bencode::as_str) != Some(FILE_FORMAT) {
+            return Err("not a resume file".to_string());
+        }
+        let version = resume.get("file-version").and_then(Bencode::as_int);
+        if version != Some(FILE_VERSION) {
+            return Err(format!("unsupported resume file version {version:?}"));
+        }
+        if resume.get("info-hash").and_then(Bencode::as_bytes) != Some(metainfo.info_hash.as_bytes()) {
+            return Err("resume file is for another torrent".to_string());
+        }
+        let bytes = |key: &str| resume.get(key).and_then(Bencode::as_bytes).unwrap_or_default();
+        let int = |value: &Bencode, key: &str| {
+            value.get(key).and_then(Bencode::as_int).and_then(|n| u64::try_from(n).ok())
+        };
+        let list = |key: &str| resume.get(key).and_then(Bencode::as_list).unwrap_or_default();
+
+        let pieces = Bitfield::from_bytes(bytes("pieces"), metainfo.info.piece_count())?;
+        let files = list("files")
+            .iter()
+            .map(|file| {
+                let length = int(file, "size")?;
+                let mtime = file.get("mtime")?.as_int()?;
+                Some(FileState { length, mtime })
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or("invalid file entry in resume file")?;
+        let unfinished = list("unfinished")
+            .iter()
+            .filter_map(|entry| {
+                let piece = u32::try_from(int(entry, "piece")?).ok()?;
+                let size = metainfo.info.piece_size(piece as usize)?;
+                let blocks = entry.get("blocks")?.as_bytes()?;
+                let blocks = Bitfield::from_bytes(blocks, size.div_ceil(BLOCK_SIZE as u64) as usize).ok()?;
+                Some((piece, blocks))
+            })
+            .collect();
+        let mut peers = compact_peers(bytes("peers"), 4);
+        peers.extend(compact_peers(bytes("peers6"), 16));
+        let trackers = list("trackers")
+            .iter()
+            .filter_map(Bencode::as_list)
+            .map(|tier| tier.iter().filter_map(Bencode::as_str).map(str::to_string).collect())
+            .collect();
+        let stats = Stats {
+            uploaded: int(&resume, "uploaded").unwrap_or(0),
+            downloaded: int(&resume, "downloaded").unwrap_or(0),
+            active_time: Duration::from_secs(int(&resume, "active-time").unwrap_or(0)),
+        };
+        Ok(ResumeData { info_hash: metainfo.info_hash, pieces, files, unfinished, peers, trackers, stats })
+    }
+
+    /// Written to a temporary file first, a crash never leaves a torn
+    /// resume file behind.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("resume.tmp");
+        fs::write(&temporary, self.to_bytes()?).map_err(|e| format!("writing {}: {e}", temporary.display()))?;
+        fs::rename(&temporary, path).map_err(|e| format!("renaming to {}: {e}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>, metainfo: &Metainfo) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| format!("reading {}: {e}", path.display()))?;
+        Self::from_bytes(&data, metainfo)
+    }
+
+    /// Whether every file still has the size and modification time it had
+    /// when this was saved.
+    pub fn files_unchanged(&self, storage: &Storage) -> bool {
+        self.files == storage.file_states()
+    }
+}
+
+/// Marks our pieces in `picker`. They come from the resume file at `path`
+/// when it is valid and the files are unchanged, otherwise every piece is
+/// hashed. Returns the resume data for its peers, trackers and stats,
+/// `None` if there was no usable file.
+pub fn restore(
+    path: impl AsRef<Path>,
+    metainfo: &Metainfo,
+    storage: &mut Storage,
+    picker: &mut PiecePicker,
+) -> Option<ResumeData> {
+    let Ok(mut resume) = ResumeData::load(path, metainfo) else {
+        mark_pieces(picker, &storage.recheck());
+        return None;
+    };
+    if resume.files_unchanged(storage) {
+        for (piece, blocks) in &resume.unfinished {
+            picker.restore_blocks(*piece, blocks);
+        }
+    } else {
+        resume.pieces = storage.recheck();
+        resume.unfinished.clear();
+    }
+    mark_pieces(picker, &resume.pieces);
+    Some(resume)
+}
+
+fn mark_pieces(picker: &mut PiecePicker, pieces: &Bitfield) {
+    for piece in 0..pieces.len() {
+        if pieces.has(piece) {
+            picker.mark_have(piece as u32);
+        }
+    }
+}
+
+/// Compact IPv4 and IPv6 peer lists.
+fn encode_peers(peers: &[SocketAddr]) -> (Vec<u8>, Vec<u8>) {
+    let (mut v4, mut v6) = (Vec::new(), Vec::new());
+    for peer in peers {
+        match peer {
+            SocketAddr::V4(peer) => {
+                v4.extend_from_slice(&peer.ip().octets());
+                v4.extend_from_slice(&peer.port().to_be_bytes());
+            }
+            SocketAddr::V6(peer) => {
+                v6.extend_from_slice(&peer.ip().octets());
+                v6.extend_from_slice(&peer.port().to_be_bytes());
+            }
+        }
+    }
+    (v4, v6)
+}
+
+#[test]
+fn test_save_and_restore() {
+    use crate::piece_picker::{Block, PickMode};
+    use crate::storage::Allocation;
+
+    let a: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+    let b: Vec<u8> = (0..10_000u32).map(|i| (i % 13) as u8).collect();
+    let (root, metainfo) = crate::storage::test_torrent("resume", &a, &b, |creator| creator.piece_length(32 * 1024));
+
+    // Piece 0 complete, the first of the two blocks of piece 1 received.
+    let download = root.join("download");
+    let data = [&a[..], &b[..]].concat();
+    let mut storage = Storage::new(&metainfo.info, &download, Allocation::Sparse).unwrap();
+    let mut picker = PiecePicker::new(metainfo.info.piece_length, metainfo.info.total_length());
+    picker.set_mode(PickMode::Sequential);
+    let peer = SocketAddr::from(([10, 0, 0, 1], 6881));
+    let mut all = Bitfield::new(2);
+    all.set(0);
+    all.set(1);
+    for block in picker.pick(peer, &all, 3) {
+        let begin = block.piece as usize * 32 * 1024 + block.begin as usize;
+        storage.write_block(block.piece, block.begin, &data[begin..begin + block.length as usize]).unwrap();
+        if picker.block_received(peer, block).piece_complete {
+            assert!(storage.finish_piece(&mut picker, block.piece).unwrap());
+        }
+    }
+    assert_eq!(picker.unfinished().len(), 1);
+
+    let mut resume = ResumeData::capture(metainfo.info_hash, &mut storage, &picker).unwrap();
+    resume.peers = vec![peer, "[2001:db8::1]:51413".parse().unwrap()];
+    resume.trackers = vec![vec!["udp://one:80".to_string(), "http://two/announce".to_string()], vec![]];
+    resume.stats = Stats { uploaded: 1 << 40, downloaded: 50_000, active_time: Duration::from_secs(3600) };
+    let path = ResumeData::path(&root, &metainfo.info_hash);
+    resume.save(&path).unwrap();
+    assert_eq!(ResumeData::load(&path, &metainfo).unwrap(), resume);
+
+    // Unchanged files, nothing is hashed and the received block is kept.
+    let mut restored = PiecePicker::new(metainfo.info.piece_length, metainfo.info.total_length());
+    restored.set_mode(PickMode::Sequential);
+    assert_eq!(restore(&path, &metainfo, &mut storage, &mut restored), Some(resume.clone()));
+    assert!(restored.have(0) && !restored.have(1));
+    assert_eq!(restored.pick(peer, &all, 2), vec![Block { piece: 1, begin: BLOCK_SIZE, length: 50_000 - 49_152 }]);
+
+    // A changed file forces a recheck, only piece 1 touches b.bin.
+    fs::write(download.join("data/sub/b.bin"), b"truncated").unwrap();
+    let mut rechecked = PiecePicker::new(metainfo.info.piece_length, metainfo.info.total_length());
+    let resume = restore(&path, &metainfo, &mut storage, &mut rechecked).unwrap();
+    assert!(rechecked.have(0) && !rechecked.have(1));
+    assert!(resume.unfinished.is_empty() && rechecked.unfinished().is_empty());
+    assert_eq!(resume.peers.len(), 2);
+
+    fs::write(&path, b"d4:spam4:eggse").unwrap();
+    let mut fresh = PiecePicker::new(metainfo.info.piece_length, metainfo.info.total_length());
+    assert_eq!(restore(&path, &metainfo, &mut storage, &mut fresh), None);
+    assert!(fresh.have(0));
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,396 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use bencoding::metainfo::PIECE_HASH_LEN;
+use bencoding::{FileLayout, InfoDict};
+use sha1::{Digest, Sha1};
+
+use crate::piece_picker::{Bitfield, PiecePicker};
+
+/// Zeros written at a time when preallocating.
+const ZERO_CHUNK: usize = 1024 * 1024;
+
+/// How files get their space on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Allocation {
+    /// Files are extended to their size without writing, the filesystem
+    /// allocates blocks as data arrives.
+    #[default]
+    Sparse,
+    /// Files are filled with zeros up front, so a full disk shows before the
+    /// download starts and files are less fragmented.
+    Preallocate,
+}
+
+/// Size and modification time of a file on disk, zeros when it is missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FileState {
+    pub length: u64,
+    /// Seconds since the Unix epoch.
+    pub mtime: i64,
+}
+
+/// File of the torrent, placed in the byte stream of all pieces.
+#[derive(Debug)]
+struct StorageFile {
+    path: PathBuf,
+    /// Offset of the first byte in the torrent.
+    offset: u64,
+    length: u64,
+    /// BEP 47 padding, reads as zeros and is never created.
+    padding: bool,
+    handle: Option<File>,
+}
+
+/// Part of a piece range inside one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    file: usize,
+    offset: u64,
+    length: usize,
+}
+
+/// Files of a torrent on disk, addressed by piece and offset.
+#[derive(Debug)]
+pub struct Storage {
+    files: Vec<StorageFile>,
+    piece_length: u64,
+    total_length: u64,
+    hashes: Vec<[u8; PIECE_HASH_LEN]>,
+    allocation: Allocation,
+}
+
+impl Storage {
+    /// Files of `info` below `root`: `root/name` for a single file,
+    /// `root/name/path...` otherwise. Nothing is touched on disk yet.
+    pub fn new(info: &InfoDict, root: impl AsRef<Path>, allocation: Allocation) -> Result<Self, String> {
+        if info.pieces.is_empty() && info.total_length() > 0 {
+            return Err("torrent has no v1 piece hashes".to_string());
+        }
+        let base = root.as_ref().join(path_component(&info.name)?);
+        let entries = match &info.layout {
+            FileLayout::Single { length } => vec![(base, *length, false)],
+            FileLayout::Multi { files } => files
+                .iter()
+                .map(|file| {
+                    let path = file.path.iter().try_fold(base.clone(), |path, part| {
+                        path_component(part).map(|part| path.join(part))
+                    });
+                    path.map(|path| (path, file.length, file.padding))
+                })
+                .collect::<Result<Vec<_>, String>>()?,
+        };
+        let mut offset = 0;
+        let files = entries
+            .into_iter()
+            .map(|(path, length, padding)| {
+                let file = StorageFile { path, offset, length, padding, handle: None };
+                offset += length;
+                file
+            })
+            .collect();
+        Ok(Storage {
+            files,
+            piece_length: info.piece_length,
+            total_length: offset,
+            hashes: info.pieces.clone(),
+            allocation,
+        })
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Creates every file at its full size, padding files aside. Data already
+    /// on disk is kept.
+    pub fn allocate(&mut self) -> Result<(), String> {
+        let allocation = self.allocation;
+        for index in 0..self.files.len() {
+            if self.files[index].padding {
+                continue;
+            }
+            let length = self.files[index].length;
+            let path = self.files[index].path.clone();
+            let file = self.open(index, true)?.expect("created");
+            let current = file.metadata().map_err(|e| format!("{}: {e}", path.display()))?.len();
+            if current >= length {
+                continue;
+            }
+            let result = match allocation {
+                Allocation::Sparse => file.set_len(length),
+                Allocation::Preallocate => write_zeros(file, current, length),
+            };
+            result.map_err(|e| format!("allocating {}: {e}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Writes a received block. Parts that fall into padding files are
+    /// dropped.
+    pub fn write_block(&mut self, piece: u32, begin: u32, data: &[u8]) -> Result<(), String> {
+        let mut written = 0;
+        for segment in self.segments(piece, begin, data.len())? {
+            let chunk = &data[written..written + segment.length];
+            written += segment.length;
+            if self.files[segment.file].padding {
+                continue;
+            }
+            let path = self.files[segment.file].path.clone();
+            let file = self.open(segment.file, true)?.expect("created");
+            file.seek(SeekFrom::Start(segment.offset))
+                .and_then(|_| file.write_all(chunk))
+                .map_err(|e| format!("writing {}: {e}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Reads `length` bytes of a piece, fails when a file is missing or
+    /// too short.
+    pub fn read_block(&mut self, piece: u32, begin: u32, length: usize) -> Result<Vec<u8>, String> {
+        let mut data = vec![0; length];
+        let mut read = 0;
+        for segment in self.segments(piece, begin, length)? {
+            let chunk = &mut data[read..read + segment.length];
+            read += segment.length;
+            if self.files[segment.file].padding {
+                continue;
+            }
+            let path = self.files[segment.file].path.clone();
+            let Some(file) = self.open(segment.file, false)? else {
+                return Err(format!("{} is missing", path.display()));
+            };
+            file.seek(SeekFrom::Start(segment.offset))
+                .and_then(|_| file.read_exact(chunk))
+                .map_err(|e| format!("reading {}: {e}", path.display()))?;
+        }
+        Ok(data)
+    }
+
+    /// Whether the piece on disk matches its SHA-1 from `pieces`.
+    pub fn verify_piece(&mut self, piece: u32) -> Result<bool, String> {
+        let size = self.piece_size(piece).ok_or_else(|| format!("piece {piece} out of range"))?;
+        let data = self.read_block(piece, 0, size as usize)?;
+        let hash: [u8; PIECE_HASH_LEN] = Sha1::digest(&data).into();
+        Ok(hash == self.hashes[piece as usize])
+    }
+
+    /// Checks a piece whose blocks all arrived and tells the picker: it
+    /// becomes ours, or all of it is requested again. `true` if it passed.
+    pub fn finish_piece(&mut self, picker: &mut PiecePicker, piece: u32) -> Result<bool, String> {
+        let valid = self.verify_piece(piece)?;
+        match valid {
+            true => picker.mark_have(piece),
+            false => picker.piece_failed(piece),
+        }
+        Ok(valid)
+    }
+
+    /// Hashes every piece on disk. Pieces with missing or short files are
+    /// not ours.
+    pub fn recheck(&mut self) -> Bitfield {
+        let mut have = Bitfield::new(self.piece_count());
+        for piece in 0..self.piece_count() {
+            if self.verify_piece(piece as u32).unwrap_or(false) {
+                have.set(piece);
+            }
+        }
+        have
+    }
+
+    /// Files on disk in torrent order, padding files aside.
+    pub fn file_states(&self) -> Vec<FileState> {
+        self.files.iter().filter(|file| !file.padding).map(|file| file_state(&file.path)).collect()
+    }
+
+    /// Syncs written data and closes the files.
+    pub fn flush(&mut self) -> Result<(), String> {
+        for file in &mut self.files {
+            if let Some(handle) = file.handle.take() {
+                handle.sync_all().map_err(|e| format!("syncing {}: {e}", file.path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn piece_size(&self, piece: u32) -> Option<u64> {
+        let offset = piece as u64 * self.piece_length;
+        match (piece as usize) < self.piece_count() {
+            true => Some((self.total_length - offset).min(self.piece_length)),
+            false => None,
+        }
+    }
+
+    /// Splits `length` bytes at `begin` of `piece` along file boundaries.
+    fn segments(&self, piece: u32, begin: u32, length: usize) -> Result<Vec<Segment>, String> {
+        let size = self.piece_size(piece).ok_or_else(|| format!("piece {piece} out of range"))?;
+        if begin as u64 + length as u64 > size {
+            return Err(format!("range {begin}+{length} exceeds piece {piece} of {size} bytes"));
+        }
+        let mut position = piece as u64 * self.piece_length + begin as u64;
+        let mut remaining = length as u64;
+        let mut segments = Vec::new();
+        let first = self.files.partition_point(|file| file.offset + file.length <= position);
+        for (index, file) in self.files.iter().enumerate().skip(first) {
+            if remaining == 0 {
+                break;
+            }
+            let offset = position - file.offset;
+            let take = (file.length - offset).min(remaining);
+            if take > 0 {
+                segments.push(Segment { file: index, offset, length: take as usize });
+            }
+            position += take;
+            remaining -= take;
+        }
+        Ok(segments)
+    }
+
+    /// Handle of a file, created with its directories if `create`, `None`
+    /// if it does not exist otherwise.
+    fn open(&mut self, index: usize, create: bool) -> Result<Option<&mut File>, String> {
+        let file = &mut self.files[index];
+        if file.handle.is_none() {
+            if !create && !file.path.is_file() {
+                return Ok(None);
+            }
+            if let Some(parent) = file.path.parent() {
+                fs::create_dir_all(parent).map_err(|e| format!("creating {}: {e}", parent.display()))?;
+            }
+            let handle = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&file.path)
+                .map_err(|e| format!("opening {}: {e}", file.path.display()))?;
+            file.handle = Some(handle);
+        }
+        Ok(file.handle.as_mut())
+    }
+}
+
+/// A file or directory name from the metainfo, refused if it could escape
+/// the download directory.
+fn path_component(name: &str) -> Result<&str, String> {
+    let unsafe_name = name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']);
+    match unsafe_name || Path::new(name).is_absolute() {
+        true => Err(format!("unsafe path component {name:?}")),
+        false => Ok(name),
+    }
+}
+
+fn file_state(path: &Path) -> FileState {
+    let Ok(metadata) = fs::metadata(path) else {
+        return FileState::default();
+    };
+    let mtime = metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+    FileState { length: metadata.len(), mtime: mtime.map_or(0, |mtime| mtime.as_secs() as i64) }
+}
+
+fn write_zeros(file: &mut File, from: u64, to: u64) -> std::io::Result<()> {
+    let zeros = vec![0; ZERO_CHUNK];
+    file.seek(SeekFrom::Start(from))?;
+    let mut position = from;
+    while position < to {
+        let take = (to - position).min(ZERO_CHUNK as u64) as usize;
+        file.write_all(&zeros[..take])?;
+        position += take as u64;
+    }
+    Ok(())
+}
+
+/// Fresh temp dir with `data/a.bin` and `data/sub/b.bin`, and a torrent of
+/// `data` made by `configure`.
+#[cfg(test)]
+pub(crate) fn test_torrent(
+    name: &str,
+    a: &[u8],
+    b: &[u8],
+    configure: impl FnOnce(bencoding::TorrentCreator) -> bencoding::TorrentCreator,
+) -> (PathBuf, bencoding::Metainfo) {
+    let root = std::env::temp_dir().join(format!("storage-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("data/sub")).unwrap();
+    fs::write(root.join("data/a.bin"), a).unwrap();
+    fs::write(root.join("data/sub/b.bin"), b).unwrap();
+    let created = configure(bencoding::TorrentCreator::new(root.join("data"))).create().unwrap();
+    (root, bencoding::Metainfo::from_bytes(&created.bytes).unwrap())
+}
+
+#[test]
+fn test_multi_file_blocks() {
+    use crate::piece_picker::BLOCK_SIZE;
+
+    let a: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+    let b: Vec<u8> = (0..10_000u32).map(|i| (i % 13) as u8).collect();
+    let (root, metainfo) = test_torrent("multi", &a, &b, |creator| creator);
+    let info = metainfo.info;
+
+    // The source files are already complete.
+    let mut seeding = Storage::new(&info, &root, Allocation::Sparse).unwrap();
+    assert!(seeding.recheck().is_complete());
+
+    let download = root.join("download");
+    let mut storage = Storage::new(&info, &download, Allocation::Preallocate).unwrap();
+    assert_eq!(storage.recheck().count(), 0);
+    storage.allocate().unwrap();
+    assert_eq!(fs::metadata(download.join("data/a.bin")).unwrap().len(), 40_000);
+    assert_eq!(fs::metadata(download.join("data/sub/b.bin")).unwrap().len(), 10_000);
+
+    // Piece 2 holds the end of a.bin and the start of b.bin.
+    let data = [&a[..], &b[..]].concat();
+    let mut picker = PiecePicker::new(info.piece_length, info.total_length());
+    for (piece, chunk) in data.chunks(BLOCK_SIZE as usize).enumerate() {
+        let (head, tail) = chunk.split_at(chunk.len() / 3);
+        storage.write_block(piece as u32, 0, head).unwrap();
+        storage.write_block(piece as u32, head.len() as u32, tail).unwrap();
+    }
+    assert_eq!(storage.read_block(2, 7000, 1000).unwrap(), data[2 * 16384 + 7000..2 * 16384 + 8000]);
+    assert!(storage.read_block(3, 0, 2000).is_err());
+    assert!(storage.write_block(9, 0, b"x").is_err());
+
+    storage.write_block(2, 100, b"corrupt").unwrap();
+    assert!(!storage.finish_piece(&mut picker, 2).unwrap());
+    assert!(!picker.have(2));
+    storage.write_block(2, 100, &data[2 * 16384 + 100..2 * 16384 + 107]).unwrap();
+    for piece in 0..storage.piece_count() as u32 {
+        assert!(storage.finish_piece(&mut picker, piece).unwrap());
+    }
+    assert!(picker.is_complete());
+    storage.flush().unwrap();
+    assert_eq!(fs::read(download.join("data/sub/b.bin")).unwrap(), b);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_padding_and_sparse_files() {
+    let a: Vec<u8> = (0..40_000u32).map(|i| (i % 253) as u8).collect();
+    let b: Vec<u8> = (0..10_000u32).map(|i| (i % 7) as u8).collect();
+    let (root, metainfo) = test_torrent("padding", &a, &b, |creator| creator.version(bencoding::MetaVersion::Hybrid));
+    let info = metainfo.info;
+
+    let download = root.join("download");
+    let mut storage = Storage::new(&info, &download, Allocation::Sparse).unwrap();
+    storage.allocate().unwrap();
+    assert_eq!(fs::metadata(download.join("data/a.bin")).unwrap().len(), 40_000);
+    assert!(!download.join("data/.pad").exists());
+
+    // Pieces run over the files padded to piece boundaries.
+    let padded = [&a[..], &[0; 9152], &b[..]].concat();
+    for (piece, chunk) in padded.chunks(info.piece_length as usize).enumerate() {
+        storage.write_block(piece as u32, 0, chunk).unwrap();
+    }
+    assert!(storage.recheck().is_complete());
+    assert_eq!(storage.read_block(2, 7000, 1000).unwrap(), padded[2 * 16384 + 7000..2 * 16384 + 8000]);
+    assert!(!download.join("data/.pad").exists());
+    storage.flush().unwrap();
+    assert_eq!(fs::read(download.join("data/a.bin")).unwrap(), a);
+
+    let mut info = info;
+    info.name = "..".to_string();
+    assert!(Storage::new(&info, &download, Allocation::Sparse).is_err());
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,412 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use bencoding::{InfoHash, Metainfo};
+use rand::seq::SliceRandom;
+
+use crate::network_manager::{HttpAnnounceRequest, NetworkManager};
+use crate::peer_messaging::{announce_all, Announce, AnnounceEventType, IpV4AnnounceRequestBuilder};
+
+/// Used when a tracker answers with an interval of 0.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30 * 60);
+/// A failing tracker is skipped for 30 s, doubling with every failure in a
+/// row up to an hour.
+const BACKOFF_BASE: Duration = Duration::from_secs(30);
+const BACKOFF_MAX: Duration = Duration::from_secs(60 * 60);
+/// Time one tracker gets to answer before the next one is tried. The UDP
+/// retransmission schedule alone would wait over two hours for a dead
+/// tracker and hold up failover.
+const TRACKER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Transfer counters sent with every announce.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub left: u64,
+}
+
+/// What every tracker of the torrent is told, only the event changes
+/// between announces.
+#[derive(Debug, Clone, Copy)]
+struct AnnounceParams {
+    info_hash: InfoHash,
+    peer_id: [u8; 20],
+    port: u16,
+    key: u32,
+    event: AnnounceEventType,
+    progress: Progress,
+}
+
+/// Answer of one tracker, the parts the scheduling needs.
+#[derive(Debug)]
+struct TrackerAnswer {
+    interval: Duration,
+    min_interval: Option<Duration>,
+    peers: Vec<SocketAddr>,
+}
+
+#[derive(Debug)]
+struct Tracker {
+    url: String,
+    /// Failures in a row, reset by a successful announce.
+    failures: u32,
+    retry_at: Option<Instant>,
+    /// `tracker id` of the last HTTP response, echoed back.
+    tracker_id: Option<String>,
+    /// UDP announcers, one per address family, resolved on first use.
+    udp: Vec<Announce>,
+}
+
+impl Tracker {
+    fn new(url: String) -> Self {
+        Tracker { url, failures: 0, retry_at: None, tracker_id: None, udp: Vec::new() }
+    }
+
+    fn is_backing_off(&self, now: Instant) -> bool {
+        self.retry_at.is_some_and(|retry_at| now < retry_at)
+    }
+
+    fn failed(&mut self, now: Instant) {
+        let backoff = BACKOFF_BASE.saturating_mul(2u32.saturating_pow(self.failures)).min(BACKOFF_MAX);
+        self.failures += 1;
+        self.retry_at = Some(now + backoff);
+        // Resolve again next time, the tracker may have moved.
+        self.udp.clear();
+    }
+
+    fn succeeded(&mut self) {
+        self.failures = 0;
+        self.retry_at = None;
+    }
+
+    async fn announce(&mut self, http: &NetworkManager, params: &AnnounceParams) -> Result<TrackerAnswer, String> {
+        if let Some(rest) = self.url.strip_prefix("udp://") {
+            if self.udp.is_empty() {
+                let authority = rest.split('/').next().unwrap_or(rest);
+                self.udp = Announce::new_all_families(authority).await?;
+            }
+            let request = IpV4AnnounceRequestBuilder::default()
+                .info_hash(params.info_hash.0)
+                .peer_id(params.peer_id)
+                .downloaded(params.progress.downloaded)
+                .left(params.progress.left)
+                .uploaded(params.progress.uploaded)
+                .event(params.event)
+                .key(params.key)
+                .port(params.port)
+                .build()
+                .map_err(|err| err.to_string())?;
+            let responses = announce_all(&mut self.udp, &request).await?;
+            let interval = responses.iter().map(|response| response.interval()).min().unwrap_or(0);
+            Ok(TrackerAnswer {
+                interval: interval_or_default(interval),
+                min_interval: None,
+                peers: responses.iter().flat_map(|response| response.peers()).collect(),
+            })
+        } else if self.url.starts_with("http://") || self.url.starts_with("https://") {
+            let request = HttpAnnounceRequest {
+                info_hash: params.info_hash,
+                peer_id: params.peer_id,
+                port: params.port,
+                uploaded: params.progress.uploaded,
+                downloaded: params.progress.downloaded,
+                left: params.progress.left,
+                event: params.event,
+                num_want: None,
+                key: Some(params.key),
+                tracker_id: self.tracker_id.clone(),
+            };
+            let response = http.announce(&self.url, &request).await?;
+            if response.tracker_id.is_some() {
+                self.tracker_id = response.tracker_id;
+            }
+            Ok(TrackerAnswer {
+                interval: interval_or_default(response.interval),
+                min_interval: response.min_interval.map(|seconds| Duration::from_secs(seconds.into())),
+                peers: response.peers,
+            })
+        } else {
+            Err(format!("unsupported tracker {}", self.url))
+        }
+    }
+}
+
+fn interval_or_default(seconds: u32) -> Duration {
+    match seconds {
+        0 => DEFAULT_INTERVAL,
+        seconds => Duration::from_secs(seconds.into()),
+    }
+}
+
+/// Announces of one torrent (BEP 12). Tiers are tried in order and the
+/// trackers of a tier in shuffled order; the first tracker that answers is
+/// moved to the front of its tier and used from then on. The manager does
+/// not schedule itself, the torrent's task announces again once
+/// [`Self::next_announce`] is due.
+pub struct TrackerManager {
+    tiers: Vec<Vec<Tracker>>,
+    info_hash: InfoHash,
+    peer_id: [u8; 20],
+    port: u16,
+    /// Random `key`, lets trackers recognise us across IP changes.
+    key: u32,
+    http: NetworkManager,
+    started: bool,
+    stopped: bool,
+    /// Whether some announce had `left > 0`, only then is `completed` sent.
+    was_incomplete: bool,
+    completed_sent: bool,
+    last_announce: Option<Instant>,
+    min_interval: Option<Duration>,
+    next_announce: Instant,
+    tracker_timeout: Duration,
+}
+
+impl TrackerManager {
+    pub fn new(tiers: Vec<Vec<String>>, info_hash: InfoHash, peer_id: [u8; 20], port: u16) -> Result<Self, String> {
+        let mut rng = rand::thread_rng();
+        let tiers = tiers
+            .into_iter()
+            .filter(|tier| !tier.is_empty())
+            .map(|tier| {
+                let mut tier: Vec<Tracker> = tier.into_iter().map(Tracker::new).collect();
+                tier.shuffle(&mut rng);
+                tier
+            })
+            .collect();
+        Ok(TrackerManager {
+            tiers,
+            info_hash,
+            peer_id,
+            port,
+            key: rand::random(),
+            http: NetworkManager::new()?,
+            started: false,
+            stopped: false,
+            was_incomplete: false,
+            completed_sent: false,
+            last_announce: None,
+            min_interval: None,
+            next_announce: Instant::now(),
+            tracker_timeout: TRACKER_TIMEOUT,
+        })
+    }
+
+    /// `announce-list` tiers, or a single tier with `announce` when the
+    /// torrent has no list (BEP 12 says to ignore `announce` then).
+    pub fn from_metainfo(metainfo: &Metainfo, peer_id: [u8; 20], port: u16) -> Result<Self, String> {
+        let tiers = match metainfo.announce_list.is_empty() {
+            true => metainfo.announce.iter().map(|url| vec![url.clone()]).collect(),
+            false => metainfo.announce_list.clone(),
+        };
+        Self::new(tiers, metainfo.info_hash, peer_id, port)
+    }
+
+    /// Tracker urls by tier, working trackers first, to be saved and passed
+    /// back to [`Self::new`].
+    pub fn tiers(&self) -> Vec<Vec<String>> {
+        self.tiers.iter().map(|tier| tier.iter().map(|tracker| tracker.url.clone()).collect()).collect()
+    }
+
+    /// When the next regular announce is due, `None` after [`Self::stop`].
+    /// After a failed announce this is when the first tracker may be
+    /// retried.
+    pub fn next_announce(&self) -> Option<Instant> {
+        (!self.stopped).then_some(self.next_announce)
+    }
+
+    /// Announces and returns the peers. The event follows from the state:
+    /// `started` first, `completed` once when `left` drops to 0, none for
+    /// regular announces. Regular announces earlier than `min interval`
+    /// after the last one are refused.
+    pub async fn announce(&mut self, progress: Progress) -> Result<Vec<SocketAddr>, String> {
+        let event = if !self.started {
+            AnnounceEventType::STARTED
+        } else if progress.left == 0 && self.was_incomplete && !self.completed_sent {
+            AnnounceEventType::COMPLETED
+        } else {
+            AnnounceEventType::UNDEFINED
+        };
+        if event == AnnounceEventType::UNDEFINED {
+            if let (Some(last), Some(min_interval)) = (self.last_announce, self.min_interval) {
+                if last.elapsed() < min_interval {
+                    return Err("announce before min interval".to_string());
+                }
+            }
+        }
+        self.stopped = false;
+        let answer = self.announce_event(event, progress).await?;
+        self.started = true;
+        self.completed_sent |= event == AnnounceEventType::COMPLETED;
+        self.was_incomplete |= progress.left > 0;
+        Ok(answer.peers)
+    }
+
+    /// Tells the tracker we are leaving the swarm. Nothing is sent when the
+    /// torrent was never announced.
+    pub async fn stop(&mut self, progress: Progress) -> Result<(), String> {
+        if !self.started || self.stopped {
+            return Ok(());
+        }
+        self.stopped = true;
+        self.started = false;
+        self.announce_event(AnnounceEventType::STOPPED, progress).await.map(|_| ())
+    }
+
+    async fn announce_event(&mut self, event: AnnounceEventType, progress: Progress) -> Result<TrackerAnswer, String> {
+        let params = AnnounceParams {
+            info_hash: self.info_hash,
+            peer_id: self.peer_id,
+            port: self.port,
+            key: self.key,
+            event,
+            progress,
+        };
+        let mut errors = Vec::new();
+        for tier in self.tiers.iter_mut() {
+            for index in 0..tier.len() {
+                let tracker = &mut tier[index];
+                if tracker.is_backing_off(Instant::now()) {
+                    continue;
+                }
+                let answer = tokio::time::timeout(self.tracker_timeout, tracker.announce(&self.http, &params))
+                    .await
+                    .unwrap_or_else(|_| Err("timed out".to_string()));
+                match answer {
+                    Ok(answer) => {
+                        tracker.succeeded();
+                        let tracker = tier.remove(index);
+                        tier.insert(0, tracker);
+                        let now = Instant::now();
+                        self.last_announce = Some(now);
+                        self.min_interval = answer.min_interval;
+                        self.next_announce = now + answer.interval;
+                        return Ok(answer);
+                    }
+                    Err(err) => {
+                        tracker.failed(Instant::now());
+                        errors.push(format!("{}: {err}", tracker.url));
+                    }
+                }
+            }
+        }
+        let now = Instant::now();
+        self.next_announce = self.tiers.iter().flatten().filter_map(|tracker| tracker.retry_at).min().unwrap_or(now);
+        match errors.is_empty() {
+            true if self.tiers.is_empty() => Err("torrent has no trackers".to_string()),
+            true => Err("every tracker is backing off".to_string()),
+            false => Err(errors.join("; ")),
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_manager(tiers: Vec<Vec<String>>) -> TrackerManager {
+    TrackerManager::new(tiers, InfoHash([3; 20]), crate::peer_id::generate(), 6881).unwrap()
+}
+
+#[tokio::test]
+async fn test_tiers_and_events() {
+    use std::sync::{Arc, Mutex};
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let log = requests.clone();
+    let tracker = crate::network_manager::spawn_fake_http_tracker(move |target| {
+        log.lock().unwrap().push(target.to_string());
+        let mut body = b"d8:intervali1800e12:min intervali60e5:peers6:".to_vec();
+        body.extend_from_slice(&[10, 0, 0, 1, 0x1a, 0xe1]);
+        body.extend_from_slice(b"e");
+        ("200 OK".to_string(), body)
+    })
+    .await;
+    // Nothing listens on port 1, connecting fails right away.
+    let dead = "http://127.0.0.1:1/announce".to_string();
+    let working = format!("http://{tracker}/announce");
+    let backup = format!("http://{tracker}/backup/announce");
+    let mut manager = test_manager(vec![vec![dead.clone(), working.clone()], vec![backup]]);
+
+    let progress = Progress { uploaded: 0, downloaded: 0, left: 100 };
+    let peers = manager.announce(progress).await.unwrap();
+    assert_eq!(peers, vec!["10.0.0.1:6881".parse::<SocketAddr>().unwrap()]);
+    assert_eq!(manager.tiers[0][0].url, working);
+    assert!(requests.lock().unwrap()[0].contains("&event=started"));
+    let next = manager.next_announce().unwrap();
+    assert!(next > Instant::now() + Duration::from_secs(1700));
+
+    // Too early for a regular announce.
+    assert!(manager.announce(progress).await.is_err());
+    manager.last_announce = Some(Instant::now() - Duration::from_secs(61));
+    manager.announce(progress).await.unwrap();
+    assert!(!requests.lock().unwrap()[1].contains("event="));
+
+    // `completed` is sent only once.
+    let done = Progress { uploaded: 0, downloaded: 100, left: 0 };
+    manager.announce(done).await.unwrap();
+    assert!(requests.lock().unwrap()[2].contains("&event=completed"));
+    manager.last_announce = None;
+    manager.announce(done).await.unwrap();
+    assert!(!requests.lock().unwrap()[3].contains("event="));
+
+    manager.stop(done).await.unwrap();
+    assert!(requests.lock().unwrap()[4].contains("&event=stopped"));
+    assert_eq!(manager.next_announce(), None);
+    manager.stop(done).await.unwrap();
+    assert_eq!(requests.lock().unwrap().len(), 5);
+    assert!(requests.lock().unwrap().iter().all(|target| target.starts_with("/announce?")));
+
+    // With the first tier down the next one is used.
+    manager.tiers[0].retain(|tracker| tracker.url == dead);
+    manager.tiers[0][0].retry_at = None;
+    manager.announce(progress).await.unwrap();
+    assert!(requests.lock().unwrap()[5].starts_with("/backup/announce?"));
+}
+
+#[tokio::test]
+async fn test_tracker_backoff() {
+    let tier = vec!["http://127.0.0.1:1/announce".to_string(), "wss://x/announce".to_string()];
+    let mut manager = test_manager(vec![tier]);
+    let progress = Progress::default();
+    let started = Instant::now();
+    let err = manager.announce(progress).await.unwrap_err();
+    assert!(err.contains("unsupported tracker wss://x/announce"));
+    assert!(manager.tiers[0].iter().all(|tracker| tracker.failures == 1));
+    let retry = manager.next_announce().unwrap();
+    assert!(retry >= started + BACKOFF_BASE);
+
+    assert_eq!(manager.announce(progress).await.unwrap_err(), "every tracker is backing off");
+    for tracker in &mut manager.tiers[0] {
+        tracker.retry_at = None;
+    }
+    manager.announce(progress).await.unwrap_err();
+    let tracker = &manager.tiers[0][0];
+    assert_eq!(tracker.failures, 2);
+    assert!(tracker.retry_at.unwrap() >= Instant::now() + 2 * BACKOFF_BASE - Duration::from_secs(1));
+
+    let mut failures = Tracker::new("http://x/announce".to_string());
+    for _ in 0..20 {
+        failures.failed(Instant::now());
+    }
+    assert!(failures.retry_at.unwrap() <= Instant::now() + BACKOFF_MAX);
+
+    assert_eq!(test_manager(Vec::new()).announce(progress).await.unwrap_err(), "torrent has no trackers");
+}
+
+#[tokio::test]
+async fn test_silent_udp_tracker_fails_over() {
+    let tracker = crate::network_manager::spawn_fake_http_tracker(|_| {
+        ("200 OK".to_string(), b"d8:intervali1800e5:peers0:e".to_vec())
+    })
+    .await;
+    // Bound but never read, every UDP packet goes unanswered.
+    let silent = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let silent_url = format!("udp://{}/announce", silent.local_addr().unwrap());
+    let mut manager = test_manager(vec![vec![silent_url], vec![format!("http://{tracker}/announce")]]);
+    manager.tracker_timeout = Duration::from_millis(200);
+
+    let started = Instant::now();
+    assert_eq!(manager.announce(Progress::default()).await.unwrap(), Vec::<SocketAddr>::new());
+    assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+    assert_eq!(manager.tiers[0][0].failures, 1);
+    assert_eq!(manager.tiers[1][0].failures, 0);
+}